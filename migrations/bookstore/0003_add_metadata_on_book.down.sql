@@ -0,0 +1,4 @@
+ALTER TABLE
+  book
+DROP
+  COLUMN metadata;
@@ -0,0 +1,7 @@
+DROP TRIGGER todo_changed ON todos;
+
+DROP FUNCTION notify_todo_changed();
+
+DROP TRIGGER book_changed ON book;
+
+DROP FUNCTION notify_book_changed();
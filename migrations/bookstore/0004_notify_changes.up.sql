@@ -0,0 +1,38 @@
+-- Publish every write on book and todos with pg_notify so all server instances
+-- can forward the change to their GraphQL subscribers.
+-- Payloads only carry the key, listeners load the row themselves (pg_notify payloads are limited to 8000 bytes).
+CREATE OR REPLACE FUNCTION notify_book_changed() RETURNS trigger AS $$
+DECLARE
+  changed_isbn varchar;
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    changed_isbn := OLD.isbn;
+  ELSE
+    changed_isbn := NEW.isbn;
+  END IF;
+  PERFORM pg_notify('book_changed', json_build_object('op', TG_OP, 'isbn', changed_isbn)::text);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER book_changed
+AFTER INSERT OR UPDATE OR DELETE ON book
+FOR EACH ROW EXECUTE FUNCTION notify_book_changed();
+
+CREATE OR REPLACE FUNCTION notify_todo_changed() RETURNS trigger AS $$
+DECLARE
+  changed_id bigint;
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    changed_id := OLD.id;
+  ELSE
+    changed_id := NEW.id;
+  END IF;
+  PERFORM pg_notify('todo_changed', json_build_object('op', TG_OP, 'id', changed_id)::text);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todo_changed
+AFTER INSERT OR UPDATE OR DELETE ON todos
+FOR EACH ROW EXECUTE FUNCTION notify_todo_changed();
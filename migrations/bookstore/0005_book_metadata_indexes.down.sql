@@ -0,0 +1,3 @@
+DROP INDEX book_metadata_avg_review_idx;
+
+DROP INDEX book_metadata_tags_idx;
@@ -0,0 +1,5 @@
+-- Indexes backing `BookFilter`, the expressions must match the ones built in `db::filter`.
+-- Default jsonb_ops (not jsonb_path_ops) so both `?|` and `@>` can use the index.
+CREATE INDEX book_metadata_tags_idx ON book USING GIN ((metadata -> 'tags'));
+
+CREATE INDEX book_metadata_avg_review_idx ON book (((metadata ->> 'avg_review')::float8));
@@ -0,0 +1,6 @@
+DROP INDEX book_search_idx;
+
+ALTER TABLE
+  book
+DROP
+  COLUMN search;
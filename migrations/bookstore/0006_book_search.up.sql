@@ -0,0 +1,15 @@
+-- Full text search over title, author and metadata tags, ranked in that order of importance.
+-- The 'simple' configuration keeps words as they are (no stemming) so prefix search on fragments works.
+ALTER TABLE
+  book
+ADD
+  COLUMN search tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', title), 'A') ||
+    setweight(to_tsvector('simple', author), 'B') ||
+    setweight(
+      jsonb_to_tsvector('simple', coalesce(metadata -> 'tags', '[]'::jsonb), '["string"]'),
+      'C'
+    )
+  ) STORED;
+
+CREATE INDEX book_search_idx ON book USING GIN (search);
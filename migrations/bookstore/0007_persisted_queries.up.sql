@@ -0,0 +1,6 @@
+-- Store of automatic persisted queries shared by all server instances, keyed by the sha256 hash of the query
+CREATE TABLE persisted_queries (
+  sha256_hash CHAR(64) PRIMARY KEY,
+  query TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
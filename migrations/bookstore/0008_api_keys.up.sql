@@ -0,0 +1,13 @@
+-- Long lived credentials of service clients, only the sha256 hash of a key is stored
+CREATE TABLE api_keys (
+  id BIGSERIAL PRIMARY KEY,
+  key_hash CHAR(64) NOT NULL UNIQUE,
+  -- Start of the key, to recognize keys in listings without storing them
+  prefix TEXT NOT NULL,
+  owner TEXT NOT NULL,
+  scopes TEXT[] NOT NULL DEFAULT '{}',
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  expires_at TIMESTAMPTZ,
+  revoked_at TIMESTAMPTZ,
+  last_used_at TIMESTAMPTZ
+);
@@ -0,0 +1,12 @@
+-- Only the constraint, the moved books and the function are undone. Rewriting `book.isbn` to the
+-- ISBN-13 is one-way, the spellings the books had before are lost.
+ALTER TABLE book DROP CONSTRAINT book_isbn_check;
+
+-- Normalized isbns are left as they are, they are valid isbns
+INSERT INTO book (title, author, isbn, metadata)
+SELECT title, author, isbn, metadata FROM book_invalid_isbn
+ON CONFLICT (isbn) DO NOTHING;
+
+DROP TABLE book_invalid_isbn;
+
+DROP FUNCTION isbn13(text);
@@ -0,0 +1,71 @@
+-- Store every isbn as the 13 digits of its ISBN-13, the form `Isbn` parses to.
+-- Same rules as `isbn::normalize_isbn`: hyphens and spaces are ignored, ISBN-10 are converted,
+-- NULL when the value is not a valid ISBN.
+CREATE OR REPLACE FUNCTION isbn13(input text) RETURNS text
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+  digits text := upper(translate(input, '- ', ''));
+  body text;
+  total int := 0;
+BEGIN
+  IF digits ~ '^[0-9]{9}[0-9X]$' THEN
+    FOR i IN 1..9 LOOP
+      total := total + substr(digits, i, 1)::int * (11 - i);
+    END LOOP;
+    IF (CASE (11 - total % 11) % 11 WHEN 10 THEN 'X' ELSE ((11 - total % 11) % 11)::text END)
+        <> right(digits, 1) THEN
+      RETURN NULL;
+    END IF;
+    body := '978' || left(digits, 9);
+  ELSIF digits ~ '^[0-9]{13}$' THEN
+    body := left(digits, 12);
+  ELSE
+    RETURN NULL;
+  END IF;
+
+  total := 0;
+  FOR i IN 1..12 LOOP
+    total := total + substr(body, i, 1)::int * (CASE WHEN i % 2 = 1 THEN 1 ELSE 3 END);
+  END LOOP;
+  IF length(digits) = 13 AND ((10 - total % 10) % 10)::text <> right(digits, 1) THEN
+    RETURN NULL;
+  END IF;
+  RETURN body || ((10 - total % 10) % 10)::text;
+END
+$$;
+
+-- Books which cannot be kept, an invalid isbn or another spelling of an isbn already in `book`
+CREATE TABLE book_invalid_isbn (
+  title varchar NOT NULL,
+  author varchar NOT NULL,
+  isbn varchar NOT NULL,
+  metadata jsonb,
+  reason text NOT NULL,
+  moved_at timestamptz NOT NULL DEFAULT now()
+);
+
+WITH ranked AS (
+  SELECT
+    ctid,
+    isbn13(isbn) AS normalized,
+    -- The spelling already normalized wins, then the first in isbn order
+    row_number() OVER (
+      PARTITION BY isbn13(isbn) ORDER BY isbn <> isbn13(isbn), isbn
+    ) AS rank
+  FROM book
+),
+moved AS (
+  DELETE FROM book
+  USING ranked
+  WHERE book.ctid = ranked.ctid AND (ranked.normalized IS NULL OR ranked.rank > 1)
+  RETURNING book.title, book.author, book.isbn, book.metadata, ranked.normalized
+)
+INSERT INTO book_invalid_isbn (title, author, isbn, metadata, reason)
+SELECT
+  title, author, isbn, metadata,
+  CASE WHEN normalized IS NULL THEN 'invalid isbn' ELSE 'duplicate of ' || normalized END
+FROM moved;
+
+UPDATE book SET isbn = isbn13(isbn) WHERE isbn IS DISTINCT FROM isbn13(isbn);
+
+ALTER TABLE book ADD CONSTRAINT book_isbn_check CHECK (isbn = isbn13(isbn));
@@ -0,0 +1 @@
+DROP INDEX persisted_queries_created_at_idx;
@@ -0,0 +1,2 @@
+-- Backs the pruning of the oldest persisted queries in `db::persisted_query::insert_persisted_query`
+CREATE INDEX persisted_queries_created_at_idx ON persisted_queries (created_at);
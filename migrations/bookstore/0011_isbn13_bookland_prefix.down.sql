@@ -0,0 +1,40 @@
+-- The function of 0009, which accepts any EAN-13
+CREATE OR REPLACE FUNCTION isbn13(input text) RETURNS text
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+  digits text := upper(translate(input, '- ', ''));
+  body text;
+  total int := 0;
+BEGIN
+  IF digits ~ '^[0-9]{9}[0-9X]$' THEN
+    FOR i IN 1..9 LOOP
+      total := total + substr(digits, i, 1)::int * (11 - i);
+    END LOOP;
+    IF (CASE (11 - total % 11) % 11 WHEN 10 THEN 'X' ELSE ((11 - total % 11) % 11)::text END)
+        <> right(digits, 1) THEN
+      RETURN NULL;
+    END IF;
+    body := '978' || left(digits, 9);
+  ELSIF digits ~ '^[0-9]{13}$' THEN
+    body := left(digits, 12);
+  ELSE
+    RETURN NULL;
+  END IF;
+
+  total := 0;
+  FOR i IN 1..12 LOOP
+    total := total + substr(body, i, 1)::int * (CASE WHEN i % 2 = 1 THEN 1 ELSE 3 END);
+  END LOOP;
+  IF length(digits) = 13 AND ((10 - total % 10) % 10)::text <> right(digits, 1) THEN
+    RETURN NULL;
+  END IF;
+  RETURN body || ((10 - total % 10) % 10)::text;
+END
+$$;
+
+INSERT INTO book (title, author, isbn, metadata)
+SELECT title, author, isbn, metadata FROM book_invalid_isbn
+WHERE reason = 'not a Bookland isbn'
+ON CONFLICT (isbn) DO NOTHING;
+
+DELETE FROM book_invalid_isbn WHERE reason = 'not a Bookland isbn';
@@ -0,0 +1,44 @@
+-- ISBN-13 only exist under the 978 and 979 Bookland prefixes, like in `isbn::normalize_isbn`.
+-- Other EAN-13 such as in-store codes are no isbn, even with a valid check digit.
+CREATE OR REPLACE FUNCTION isbn13(input text) RETURNS text
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+  digits text := upper(translate(input, '- ', ''));
+  body text;
+  total int := 0;
+BEGIN
+  IF digits ~ '^[0-9]{9}[0-9X]$' THEN
+    FOR i IN 1..9 LOOP
+      total := total + substr(digits, i, 1)::int * (11 - i);
+    END LOOP;
+    IF (CASE (11 - total % 11) % 11 WHEN 10 THEN 'X' ELSE ((11 - total % 11) % 11)::text END)
+        <> right(digits, 1) THEN
+      RETURN NULL;
+    END IF;
+    body := '978' || left(digits, 9);
+  ELSIF digits ~ '^97[89][0-9]{10}$' THEN
+    body := left(digits, 12);
+  ELSE
+    RETURN NULL;
+  END IF;
+
+  total := 0;
+  FOR i IN 1..12 LOOP
+    total := total + substr(body, i, 1)::int * (CASE WHEN i % 2 = 1 THEN 1 ELSE 3 END);
+  END LOOP;
+  IF length(digits) = 13 AND ((10 - total % 10) % 10)::text <> right(digits, 1) THEN
+    RETURN NULL;
+  END IF;
+  RETURN body || ((10 - total % 10) % 10)::text;
+END
+$$;
+
+-- `book_isbn_check` is not checked again when the function changes, books which no longer pass it are
+-- moved aside like the invalid ones of 0009
+WITH moved AS (
+  DELETE FROM book
+  WHERE isbn13(isbn) IS NULL
+  RETURNING title, author, isbn, metadata
+)
+INSERT INTO book_invalid_isbn (title, author, isbn, metadata, reason)
+SELECT title, author, isbn, metadata, 'not a Bookland isbn' FROM moved;
@@ -0,0 +1,23 @@
+use rand::distributions::{Alphanumeric, DistString};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+const KEY_PREFIX: &str = "bk_";
+const KEY_LENGTH: usize = 40;
+/// Characters of a key kept in the `prefix` column to recognize it in listings
+const DISPLAYED_LENGTH: usize = KEY_PREFIX.len() + 8;
+
+/// A new random API key, returns the key and its displayed prefix
+pub(crate) fn generate_api_key() -> (String, String) {
+    let key = format!(
+        "{KEY_PREFIX}{}",
+        Alphanumeric.sample_string(&mut OsRng, KEY_LENGTH)
+    );
+    let prefix = key[..DISPLAYED_LENGTH].to_string();
+    (key, prefix)
+}
+
+/// Keys are random enough for an unsalted hash, which allows looking them up by hash
+pub(crate) fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
@@ -0,0 +1,138 @@
+use crate::auth::Identity;
+use crate::config::AuthConfig;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::fmt;
+
+/// Claims read from the bearer tokens, `exp` is checked by the validation
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    roles: Vec<String>,
+    /// Space separated OAuth 2 scopes
+    #[serde(default)]
+    scope: String,
+}
+
+struct VerificationKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Verifies HS256 and RS256 bearer tokens against the configured keys
+pub(crate) struct JwtVerifier {
+    keys: Vec<VerificationKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum KeyError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Jwk(jsonwebtoken::errors::Error),
+    Unsupported(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Io(e) => write!(f, "cannot read JWKS file: {e}"),
+            KeyError::Json(e) => write!(f, "JWKS file is not a JSON Web Key Set: {e}"),
+            KeyError::Jwk(e) => write!(f, "invalid JSON Web Key: {e}"),
+            KeyError::Unsupported(kid) => {
+                write!(f, "key {kid} is neither an RSA nor a symmetric key")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+impl JwtVerifier {
+    /// Collect the keys of the JWKS file and the HS256 secret, either one may be left out
+    pub fn new(options: &AuthConfig) -> Result<Self, KeyError> {
+        let mut keys = Vec::new();
+
+        if let Some(secret) = &options.jwt_secret {
+            keys.push(VerificationKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+            });
+        }
+
+        if let Some(path) = &options.jwks_file {
+            let content = std::fs::read_to_string(path).map_err(KeyError::Io)?;
+            let jwks: JwkSet = serde_json::from_str(&content).map_err(KeyError::Json)?;
+            for jwk in &jwks.keys {
+                let algorithm = match jwk.algorithm {
+                    AlgorithmParameters::RSA(_) => Algorithm::RS256,
+                    AlgorithmParameters::OctetKey(_) => Algorithm::HS256,
+                    _ => {
+                        let kid = jwk.common.key_id.clone().unwrap_or_default();
+                        return Err(KeyError::Unsupported(kid));
+                    }
+                };
+                keys.push(VerificationKey {
+                    kid: jwk.common.key_id.clone(),
+                    algorithm,
+                    key: DecodingKey::from_jwk(jwk).map_err(KeyError::Jwk)?,
+                });
+            }
+        }
+
+        Ok(JwtVerifier {
+            keys,
+            issuer: options.jwt_issuer.clone(),
+            audience: options.jwt_audience.clone(),
+        })
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check the signature and claims of a token.
+    /// The key is picked by the `kid` of the token header, tokens without `kid` try every key of their algorithm.
+    pub fn verify(&self, token: &str) -> Result<Identity, String> {
+        let header = decode_header(token).map_err(|e| format!("malformed token: {e}"))?;
+        if !matches!(header.alg, Algorithm::HS256 | Algorithm::RS256) {
+            return Err(format!("unsupported token algorithm {:?}", header.alg));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let candidates = self.keys.iter().filter(|key| {
+            key.algorithm == header.alg
+                && (header.kid.is_none() || key.kid.is_none() || key.kid == header.kid)
+        });
+
+        let mut error = "no key to verify the token".to_string();
+        for candidate in candidates {
+            match decode::<Claims>(token, &candidate.key, &validation) {
+                Ok(data) => {
+                    let claims = data.claims;
+                    return Ok(Identity {
+                        subject: claims.sub,
+                        api_key: None,
+                        roles: claims.roles.into_iter().collect(),
+                        scopes: claims.scope.split_whitespace().map(String::from).collect(),
+                    });
+                }
+                Err(e) => error = format!("invalid token: {e}"),
+            }
+        }
+        Err(error)
+    }
+}
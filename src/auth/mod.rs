@@ -0,0 +1,160 @@
+use crate::db;
+use crate::db::api_key::use_api_key;
+use crate::rate_limit::{rate_limited_message, too_many_requests, RateLimiter};
+use crate::routes::error_response;
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::error;
+
+mod api_key;
+mod jwt;
+
+pub(crate) use api_key::{generate_api_key, hash_api_key};
+pub(crate) use jwt::JwtVerifier;
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// The authenticated caller of a request.
+/// Added to the request extensions by `authenticate` and from there to the GraphQL context.
+#[derive(Debug, Clone)]
+pub(crate) struct Identity {
+    /// JWT subject, or owner of the API key
+    pub(crate) subject: String,
+    /// Id of the API key the caller authenticated with, `None` for bearer tokens
+    pub(crate) api_key: Option<i64>,
+    pub(crate) roles: HashSet<String>,
+    pub(crate) scopes: HashSet<String>,
+}
+
+/// Everything needed to check the credentials of a request
+pub(crate) struct Authenticator {
+    pub(crate) jwt: JwtVerifier,
+    /// Where API keys are looked up, without a database only bearer tokens are accepted
+    pub(crate) pool: Option<PgPool>,
+    /// Charged for rejected credentials, by IP address
+    pub(crate) limiter: Arc<RateLimiter>,
+}
+
+enum AuthError {
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl Authenticator {
+    /// `None` when the request carries no credentials
+    async fn identify(&self, headers: &HeaderMap) -> Result<Option<Identity>, AuthError> {
+        let authorization = headers.get(header::AUTHORIZATION);
+        let api_key = headers.get(API_KEY_HEADER);
+
+        match (authorization, api_key) {
+            (None, None) => Ok(None),
+            (Some(_), Some(_)) => Err(AuthError::Invalid(
+                "send either an Authorization or an X-Api-Key header, not both".to_string(),
+            )),
+            (Some(authorization), None) => {
+                let token = authorization
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .ok_or_else(|| AuthError::Invalid("expected a bearer token".to_string()))?;
+                self.jwt
+                    .verify(token.trim())
+                    .map(Some)
+                    .map_err(AuthError::Invalid)
+            }
+            (None, Some(key)) => {
+                let pool = self.pool.as_ref().ok_or_else(|| {
+                    AuthError::Invalid("API keys are not available without a database".to_string())
+                })?;
+                let key = key.to_str().unwrap_or_default();
+                let mut conn = db::acquire(pool).await.map_err(AuthError::Database)?;
+                let key = use_api_key(&mut *conn, &hash_api_key(key))
+                    .await
+                    .map_err(AuthError::Database)?
+                    .ok_or_else(|| {
+                        AuthError::Invalid("unknown, expired or revoked API key".to_string())
+                    })?;
+
+                Ok(Some(Identity {
+                    subject: key.owner,
+                    api_key: Some(key.id),
+                    roles: HashSet::new(),
+                    scopes: key.scopes.into_iter().collect(),
+                }))
+            }
+        }
+    }
+}
+
+/// Resolve the `Authorization: Bearer` token or `X-Api-Key` of a request into an `Identity`.
+/// Requests without credentials go through anonymously, requests with invalid credentials are rejected
+/// and charged to their IP address, which is refused any further attempt once out of tokens.
+pub(crate) async fn authenticate<B>(
+    State(authenticator): State<Arc<Authenticator>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let ConnectInfo(address) = *req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .expect("the server has to be started with connect info");
+    let has_credentials = req.headers().contains_key(header::AUTHORIZATION)
+        || req.headers().contains_key(API_KEY_HEADER);
+    if has_credentials {
+        if let Some(retry_after) = authenticator.limiter.blocked(address.ip()) {
+            return rate_limited(retry_after);
+        }
+    }
+
+    match authenticator.identify(req.headers()).await {
+        Ok(identity) => {
+            if let Some(identity) = identity {
+                req.extensions_mut().insert(identity);
+            }
+            next.run(req).await
+        }
+        Err(AuthError::Invalid(message)) => {
+            match authenticator.limiter.charge_rejected(address.ip()) {
+                Ok(()) => unauthenticated(message),
+                Err(retry_after) => rate_limited(retry_after),
+            }
+        }
+        Err(AuthError::Database(e)) => {
+            error!("cannot check API key: {}", e);
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "INTERNAL_SERVER_ERROR",
+                "cannot check credentials right now".to_string(),
+            )
+        }
+    }
+}
+
+fn rate_limited(retry_after: Duration) -> Response {
+    too_many_requests(
+        error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "RATE_LIMITED",
+            rate_limited_message(retry_after),
+        ),
+        retry_after,
+    )
+}
+
+fn unauthenticated(message: String) -> Response {
+    let mut response = error_response(StatusCode::UNAUTHORIZED, "UNAUTHENTICATED", message);
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Bearer"),
+    );
+    response
+}
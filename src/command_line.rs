@@ -1,17 +1,51 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use crate::config::{PersistedQueryStoreKind, RepositoryKind};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[clap(author = "zhaowei", version, about)]
 pub struct Arguments {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
     #[clap(subcommand)]
     pub cmd: SubCommand,
 }
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum SubCommand {
-    StartServer {
-        #[arg(long, short)]
-        port: String,
+    StartServer,
+    Config {
+        #[clap(subcommand)]
+        action: ConfigAction,
+    },
+    TrustedDocuments {
+        #[clap(subcommand)]
+        action: TrustedDocumentsAction,
+    },
+    Schema {
+        #[clap(subcommand)]
+        action: SchemaAction,
+    },
+    ApiKeys {
+        #[clap(subcommand)]
+        action: ApiKeyAction,
+    },
+    /// Bulk import and export of the book catalog
+    Bookstore {
+        #[clap(subcommand)]
+        action: CatalogAction,
+    },
+    /// Load books and todos from fixture files and generate synthetic books, running it again is harmless
+    Seed {
+        /// Fixture files, the format is taken from the extension: json, yaml, yml or csv
+        files: Vec<PathBuf>,
+        /// Number of synthetic books to generate
+        #[arg(long, default_value_t = 0)]
+        synthetic: usize,
+        /// Seed of the synthetic books, the same seed gives the same books
+        #[arg(long, default_value_t = 42)]
+        rng_seed: u64,
     },
     Sqlx {
         #[clap(subcommand)]
@@ -22,12 +56,255 @@ pub enum SubCommand {
     },
 }
 
+/// Overrides of the configuration file values, see `config::Config`.
+/// Flags take precedence over environment variables, which take precedence over the file.
+#[derive(Args, Debug, Clone)]
+pub struct ConfigArgs {
+    /// TOML configuration file
+    #[arg(long = "config", short = 'c', env = "CONFIG_FILE", global = true)]
+    pub file: Option<PathBuf>,
+    #[arg(long, short, env = "PORT", global = true)]
+    pub port: Option<u16>,
+    /// Only execute the operations of this trusted documents manifest, disables automatic persisted queries
+    #[arg(long, env = "TRUSTED_DOCUMENTS", global = true)]
+    pub trusted_documents: Option<PathBuf>,
+    /// Where the API keeps books and todos, `memory` serves it without them being stored
+    #[arg(long, env = "REPOSITORY", value_enum, global = true)]
+    pub repository: Option<RepositoryKind>,
+    #[clap(flatten)]
+    pub database: DatabaseArgs,
+    #[clap(flatten)]
+    pub tracing: TracingArgs,
+    #[clap(flatten)]
+    pub limits: QueryLimitArgs,
+    #[clap(flatten)]
+    pub persisted_queries: PersistedQueryArgs,
+    #[clap(flatten)]
+    pub auth: AuthArgs,
+    #[clap(flatten)]
+    pub rate_limit: RateLimitArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Database")]
+pub struct DatabaseArgs {
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true, global = true)]
+    pub database_url: Option<String>,
+    #[arg(long = "db-max-connections", env = "DB_MAX_CONNECTIONS", global = true)]
+    pub max_connections: Option<u32>,
+    #[arg(long = "db-min-connections", env = "DB_MIN_CONNECTIONS", global = true)]
+    pub min_connections: Option<u32>,
+    #[arg(
+        long = "db-acquire-timeout-secs",
+        env = "DB_ACQUIRE_TIMEOUT_SECS",
+        global = true
+    )]
+    pub acquire_timeout_secs: Option<u64>,
+    /// 0 keeps idle connections open
+    #[arg(
+        long = "db-idle-timeout-secs",
+        env = "DB_IDLE_TIMEOUT_SECS",
+        global = true
+    )]
+    pub idle_timeout_secs: Option<u64>,
+    /// 0 never recycles connections
+    #[arg(
+        long = "db-max-lifetime-secs",
+        env = "DB_MAX_LIFETIME_SECS",
+        global = true
+    )]
+    pub max_lifetime_secs: Option<u64>,
+    #[arg(
+        long = "db-statement-cache-capacity",
+        env = "DB_STATEMENT_CACHE_CAPACITY",
+        global = true
+    )]
+    pub statement_cache_capacity: Option<usize>,
+    #[arg(
+        long = "db-application-name",
+        env = "DB_APPLICATION_NAME",
+        global = true
+    )]
+    pub application_name: Option<String>,
+    /// Apply the pending migrations when the server starts, replicas take turns with an advisory lock
+    #[arg(
+        long,
+        env = "MIGRATE_ON_START",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        global = true
+    )]
+    pub migrate_on_start: Option<bool>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Tracing")]
+pub struct TracingArgs {
+    #[arg(long, env = "JAEGER_ENABLED", global = true)]
+    pub jaeger_enabled: Option<bool>,
+    #[arg(long, env = "JAEGER_AGENT_HOST", global = true)]
+    pub jaeger_agent_host: Option<String>,
+    #[arg(long, env = "JAEGER_AGENT_PORT", global = true)]
+    pub jaeger_agent_port: Option<u16>,
+    #[arg(long, env = "TRACING_SERVICE_NAME", global = true)]
+    pub service_name: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Query limits")]
+pub struct QueryLimitArgs {
+    /// Maximum nesting depth of a query
+    #[arg(long, env = "GRAPHQL_MAX_DEPTH", global = true)]
+    pub max_depth: Option<usize>,
+    /// Maximum complexity of a query, list fields count once per expected item
+    #[arg(long, env = "GRAPHQL_MAX_COMPLEXITY", global = true)]
+    pub max_complexity: Option<usize>,
+    /// Maximum number of aliased fields in a query
+    #[arg(long, env = "GRAPHQL_MAX_ALIASES", global = true)]
+    pub max_aliases: Option<usize>,
+    /// Maximum length of a query document in bytes
+    #[arg(long, env = "GRAPHQL_MAX_QUERY_LENGTH", global = true)]
+    pub max_query_length: Option<usize>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Automatic persisted queries")]
+pub struct PersistedQueryArgs {
+    /// Where persisted queries are kept, `postgres` shares them between instances
+    #[arg(long = "apq-store", env = "APQ_STORE", value_enum, global = true)]
+    pub store: Option<PersistedQueryStoreKind>,
+    /// Number of persisted queries cached in memory
+    #[arg(long = "apq-cache-size", env = "APQ_CACHE_SIZE", global = true)]
+    pub cache_size: Option<NonZeroUsize>,
+    /// Number of persisted queries kept in Postgres, the oldest are deleted first
+    #[arg(long = "apq-max-stored", env = "APQ_MAX_STORED", global = true)]
+    pub max_stored: Option<NonZeroUsize>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Authentication")]
+pub struct AuthArgs {
+    /// Shared secret of HS256 tokens
+    #[arg(long, env = "JWT_SECRET", hide_env_values = true, global = true)]
+    pub jwt_secret: Option<String>,
+    /// JSON Web Key Set with the RSA (RS256) and symmetric (HS256) keys, matched by `kid`
+    #[arg(long, env = "JWKS_FILE", global = true)]
+    pub jwks_file: Option<PathBuf>,
+    /// Required `iss` claim
+    #[arg(long, env = "JWT_ISSUER", global = true)]
+    pub jwt_issuer: Option<String>,
+    /// Required `aud` claim
+    #[arg(long, env = "JWT_AUDIENCE", global = true)]
+    pub jwt_audience: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Rate limiting")]
+pub struct RateLimitArgs {
+    /// Tokens a client can spend in a burst
+    #[arg(
+        long = "rate-limit-capacity",
+        env = "RATE_LIMIT_CAPACITY",
+        global = true
+    )]
+    pub capacity: Option<u32>,
+    /// Tokens given back to each client per second
+    #[arg(
+        long = "rate-limit-refill",
+        env = "RATE_LIMIT_REFILL_PER_SECOND",
+        global = true
+    )]
+    pub refill_per_second: Option<u32>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigAction {
+    /// Print the effective configuration as TOML, secrets are redacted
+    Print,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TrustedDocumentsAction {
+    /// Check every document of a manifest against the current schema, exits with 1 if any is invalid
+    Validate {
+        #[arg(long, short)]
+        manifest: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SchemaAction {
+    /// Print the SDL of the GraphQL schema
+    Print {
+        /// Write the SDL to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Compare the current schema to a previous SDL, exits with 1 if any change is breaking
+    Diff {
+        #[arg(long)]
+        against: PathBuf,
+        /// Also exit with 1 on dangerous changes
+        #[arg(long)]
+        fail_on_dangerous: bool,
+    },
+}
+
+/// API keys of service clients, sent in the `X-Api-Key` header
+#[derive(Subcommand, Debug, Clone)]
+pub enum ApiKeyAction {
+    /// Create a key, it is printed once and only its hash is stored
+    Mint {
+        #[arg(long)]
+        owner: String,
+        /// Scope granted to the key, may be repeated
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+        #[arg(long)]
+        expires_in_days: Option<u32>,
+    },
+    List,
+    Revoke {
+        id: i64,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CatalogAction {
+    /// Upsert the books of a file on isbn, invalid rows are skipped and reported, exits with 1 if any was
+    Import {
+        input: PathBuf,
+        #[arg(long, value_enum, default_value_t = CatalogFormat::Csv)]
+        format: CatalogFormat,
+        /// Write the rejected rows to this CSV file instead of stderr
+        #[arg(long)]
+        rejects: Option<PathBuf>,
+    },
+    /// Write every book ordered by isbn in the format `import` reads
+    Export {
+        #[arg(long, short)]
+        output: PathBuf,
+        #[arg(long, value_enum, default_value_t = CatalogFormat::Csv)]
+        format: CatalogFormat,
+    },
+}
+
+/// `csv` has the `title,author,isbn,avg_review,tags` columns with tags separated by `|`,
+/// `jsonl` has one book object per line
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CatalogFormat {
+    Csv,
+    Jsonl,
+}
+
 #[derive(Subcommand, Debug, Clone)]
 pub enum SqlCase {
     Test,
+    /// Migrations of `migrations/bookstore`
     Migrate {
-        #[arg(long, short)]
-        folder: MigrationFolder,
+        #[clap(subcommand)]
+        action: MigrateAction,
     },
     Bookstore {
         #[clap(subcommand)]
@@ -35,6 +312,34 @@ pub enum SqlCase {
     },
 }
 
+#[derive(Subcommand, Debug, Clone)]
+pub enum MigrateAction {
+    /// Apply the pending migrations
+    Up {
+        /// Only list the migrations which would be applied
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Revert the latest migration, or every migration above `--target`
+    Down {
+        /// Version to revert to, 0 reverts everything
+        #[arg(long)]
+        target: Option<i64>,
+        /// Only list the migrations which would be reverted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the applied and pending migrations with their checksums
+    Status,
+    /// Check the database matches the embedded migrations, exits with 1 otherwise
+    Verify,
+    /// Drop every table and migrate from scratch, only allowed on a local database
+    Reset {
+        #[arg(long = "yes-i-am-sure")]
+        confirmed: bool,
+    },
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 pub enum MigrationFolder {
     Bookstore,
@@ -42,7 +347,6 @@ pub enum MigrationFolder {
 
 #[derive(Debug, Clone, Subcommand)]
 pub enum BookstoreEx {
-    Create,
     Update,
     Read {
         #[arg(short)]
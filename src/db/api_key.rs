@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// An API key as stored, the key itself is only known to its owner
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub prefix: String,
+    pub owner: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+const API_KEY_COLUMNS: &str =
+    "id, prefix, owner, scopes, created_at, expires_at, revoked_at, last_used_at";
+
+pub async fn insert_api_key(
+    pool: &sqlx::PgPool,
+    key_hash: &str,
+    prefix: &str,
+    owner: &str,
+    scopes: &[String],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<ApiKey, sqlx::Error> {
+    let sql = format!(
+        r#"
+        INSERT INTO api_keys (key_hash, prefix, owner, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING {API_KEY_COLUMNS}
+        "#
+    );
+
+    sqlx::query_as::<_, ApiKey>(&sql)
+        .bind(key_hash)
+        .bind(prefix)
+        .bind(owner)
+        .bind(scopes)
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await
+}
+
+/// All keys ordered by id, including revoked and expired ones
+pub async fn fetch_api_keys(pool: &sqlx::PgPool) -> Result<Vec<ApiKey>, sqlx::Error> {
+    let sql = format!("SELECT {API_KEY_COLUMNS} FROM api_keys ORDER BY id");
+    sqlx::query_as::<_, ApiKey>(&sql).fetch_all(pool).await
+}
+
+/// Revoke a key, returns false if there is no such key or it was already revoked
+pub async fn revoke_api_key(pool: &sqlx::PgPool, id: i64) -> Result<bool, sqlx::Error> {
+    let result =
+        sqlx::query("UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Find the usable (neither revoked nor expired) key with the given hash and record that it was used
+pub async fn use_api_key(
+    executor: impl PgExecutor<'_>,
+    key_hash: &str,
+) -> Result<Option<ApiKey>, sqlx::Error> {
+    let sql = format!(
+        r#"
+        UPDATE api_keys SET last_used_at = now()
+        WHERE key_hash = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > now())
+        RETURNING {API_KEY_COLUMNS}
+        "#
+    );
+
+    sqlx::query_as::<_, ApiKey>(&sql)
+        .bind(key_hash)
+        .fetch_optional(executor)
+        .await
+}
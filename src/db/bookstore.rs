@@ -1,21 +1,25 @@
 use crate::command_line::ExVersion;
+use crate::db::filter::{push_book_filter, BookFilter};
+use crate::isbn::Isbn;
+use async_graphql::{Enum, InputObject, SimpleObject};
 use futures::stream::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgRow;
 use sqlx::types::Json;
-use sqlx::Postgres;
 use sqlx::Row;
 use sqlx::{Decode, Encode};
+use sqlx::{PgExecutor, Postgres, QueryBuilder};
 use std::error::Error;
 
 #[allow(unused)]
 use tracing::{error, info, warn};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, SimpleObject)]
+#[graphql(complex)]
 pub struct Book {
     pub title: String,
     pub author: String,
-    pub isbn: String,
+    pub isbn: Isbn,
     pub metadata: Option<Metadata>,
 }
 
@@ -35,76 +39,389 @@ impl<'r> sqlx::FromRow<'r, PgRow> for Book {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Decode, Encode)]
+#[derive(Debug, Clone, Serialize, Deserialize, Decode, Encode, SimpleObject, InputObject)]
+#[graphql(input_name = "MetadataInput")]
 pub struct Metadata {
     pub avg_review: f32,
     pub tags: Vec<String>,
 }
 
+/// Separator of the tags in the CSV `tags` column, so it cannot appear within a tag
+pub const TAG_SEPARATOR: char = '|';
+
+impl Metadata {
+    /// Why the metadata cannot be stored, checked for imports and mutations alike
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=10.0).contains(&self.avg_review) {
+            return Err(format!(
+                "avg_review must be between 0 and 10, not {}",
+                self.avg_review
+            ));
+        }
+        if self.tags.iter().any(|tag| tag.trim().is_empty()) {
+            return Err("tags cannot be empty".to_string());
+        }
+        if let Some(tag) = self.tags.iter().find(|tag| tag.contains(TAG_SEPARATOR)) {
+            return Err(format!(
+                "tag {tag:?} contains {TAG_SEPARATOR:?}, which separates tags in CSV exports"
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl sqlx::Type<Postgres> for Metadata {
     fn type_info() -> <Postgres as sqlx::Database>::TypeInfo {
         <Postgres as sqlx::Database>::TypeInfo::with_name("metadata")
     }
 }
 
-/// Example show how to create records
-/// cargo run -- sqlx bookstore create
-pub async fn create_book_example(pool: &sqlx::PgPool) -> Result<(), Box<dyn Error>> {
-    let query = "insert into book (title, author, isbn) values ($1, $2, $3)";
-    sqlx::query(query)
-        .bind("book01".to_string())
-        .bind("fox".to_string())
-        .bind("000-111-222-33".to_string())
-        .execute(pool)
-        .await?;
+/// Fetch all books matching the optional filter ordered by title
+pub async fn fetch_books(
+    executor: impl PgExecutor<'_>,
+    filter: Option<&BookFilter>,
+) -> Result<Vec<Book>, sqlx::Error> {
+    let mut query =
+        QueryBuilder::<Postgres>::new("SELECT title, author, isbn, metadata FROM book WHERE ");
+    push_book_filter(&mut query, filter.unwrap_or(&BookFilter::default()));
+    query.push(" ORDER BY title, isbn");
+
+    query.build_query_as::<Book>().fetch_all(executor).await
+}
+
+/// Fetch all books with one of the given isbns in a single statement, used for batch loading
+pub async fn fetch_books_by_isbns(
+    executor: impl PgExecutor<'_>,
+    isbns: &[Isbn],
+) -> Result<Vec<Book>, sqlx::Error> {
+    sqlx::query_as::<_, Book>(
+        r#"
+        SELECT title, author, isbn, metadata FROM book WHERE isbn = ANY($1)
+        "#,
+    )
+    .bind(isbns)
+    .fetch_all(executor)
+    .await
+}
+
+/// Fetch all books written by one of the given authors ordered by title, used for batch loading
+pub async fn fetch_books_by_authors(
+    executor: impl PgExecutor<'_>,
+    authors: &[String],
+) -> Result<Vec<Book>, sqlx::Error> {
+    sqlx::query_as::<_, Book>(
+        r#"
+        SELECT title, author, isbn, metadata FROM book WHERE author = ANY($1) ORDER BY title, isbn
+        "#,
+    )
+    .bind(authors)
+    .fetch_all(executor)
+    .await
+}
+
+/// Sort order for paginated book listings.
+/// Every order is made unique by falling back to isbn, which keeps keyset cursors stable.
+#[derive(Enum, Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BookOrder {
+    #[default]
+    Isbn,
+    Title,
+}
+
+/// Position of a book within a `BookOrder`, used as keyset for pagination
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookKey {
+    pub title: String,
+    pub isbn: Isbn,
+}
+
+impl From<&Book> for BookKey {
+    fn from(book: &Book) -> Self {
+        BookKey {
+            title: book.title.clone(),
+            isbn: book.isbn.clone(),
+        }
+    }
+}
+
+/// One page of books and whether more books exist past the end of it
+#[derive(Debug)]
+pub struct BookPage {
+    pub books: Vec<Book>,
+    pub has_more: bool,
+}
+
+/// Fetch at most `limit` books matching `filter` strictly between `after` and `before` in the given order.
+/// With `from_end` the page is taken from the end of the range (`last`/`before` in Relay terms),
+/// the returned books are still in ascending order.
+/// Rows are streamed with `fetch` like `fetch_books_v3`, so only one extra row is read to detect `has_more`.
+pub async fn fetch_books_page(
+    executor: impl PgExecutor<'_>,
+    order: BookOrder,
+    filter: Option<&BookFilter>,
+    after: Option<&BookKey>,
+    before: Option<&BookKey>,
+    limit: usize,
+    from_end: bool,
+) -> Result<BookPage, sqlx::Error> {
+    let mut query =
+        QueryBuilder::<Postgres>::new("SELECT title, author, isbn, metadata FROM book WHERE ");
+    push_book_filter(&mut query, filter.unwrap_or(&BookFilter::default()));
+
+    if let Some(key) = after {
+        push_key_bound(&mut query, order, key, ">");
+    }
+    if let Some(key) = before {
+        push_key_bound(&mut query, order, key, "<");
+    }
+
+    let direction = if from_end { "DESC" } else { "ASC" };
+    match order {
+        BookOrder::Isbn => query.push(format_args!(" ORDER BY isbn {direction}")),
+        BookOrder::Title => query.push(format_args!(
+            " ORDER BY title {direction}, isbn {direction}"
+        )),
+    };
+    query.push(" LIMIT ").push_bind(limit as i64 + 1);
+
+    let mut books = Vec::with_capacity(limit);
+    let mut has_more = false;
+    let mut book_stream = query.build_query_as::<Book>().fetch(executor);
+    while let Some(book) = book_stream.next().await {
+        let book = book?;
+        if books.len() == limit {
+            has_more = true;
+            break;
+        }
+        books.push(book);
+    }
+
+    if from_end {
+        books.reverse();
+    }
+
+    Ok(BookPage { books, has_more })
+}
+
+fn push_key_bound(
+    query: &mut QueryBuilder<'_, Postgres>,
+    order: BookOrder,
+    key: &BookKey,
+    op: &str,
+) {
+    match order {
+        BookOrder::Isbn => {
+            query
+                .push(format_args!(" AND isbn {op} "))
+                .push_bind(key.isbn.clone());
+        }
+        BookOrder::Title => {
+            query
+                .push(format_args!(" AND (title, isbn) {op} ("))
+                .push_bind(key.title.clone())
+                .push(", ")
+                .push_bind(key.isbn.clone())
+                .push(")");
+        }
+    }
+}
+
+/// A book matching a full text search
+#[derive(Debug, Clone, SimpleObject)]
+pub struct SearchHit {
+    pub book: Book,
+    /// `ts_rank` of the match, higher is better
+    pub rank: f32,
+    /// Title and author with the matching words wrapped in `<b>` tags
+    pub headline: String,
+}
+
+impl<'r> sqlx::FromRow<'r, PgRow> for SearchHit {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(SearchHit {
+            book: Book::from_row(row)?,
+            rank: row.try_get("rank")?,
+            headline: row.try_get("headline")?,
+        })
+    }
+}
 
-    let book = Book {
-        title: "A Game of Thrones".to_string(),
-        author: "Martin".to_string(),
-        isbn: "111-222-333-444".to_string(),
-        metadata: Some(Metadata {
-            avg_review: 9.4,
-            tags: vec!["fantasy".to_string(), "epic".to_string()],
-        }),
+/// Search books by fragments of their title, author or tags using the `search` column
+/// from `0006_book_search.sql`. Every word of `text` must match the start of a word in the book.
+pub async fn search_books(
+    executor: impl PgExecutor<'_>,
+    text: &str,
+    limit: i64,
+) -> Result<Vec<SearchHit>, sqlx::Error> {
+    let Some(tsquery) = prefix_tsquery(text) else {
+        return Ok(vec![]);
     };
-    let q = r#"
-        insert into book (title, author, isbn, metadata) values ($1, $2, $3, $4)
-        "#;
-    sqlx::query(q)
-        .bind(&book.title)
-        .bind(&book.author)
-        .bind(&book.isbn)
-        .bind(serde_json::to_value(&book.metadata).unwrap())
-        .execute(pool)
+
+    sqlx::query_as::<_, SearchHit>(
+        r#"
+        SELECT title, author, isbn, metadata,
+            ts_rank(search, query) AS rank,
+            ts_headline('simple', title || ' - ' || author, query) AS headline
+        FROM book, to_tsquery('simple', $1) query
+        WHERE search @@ query
+        ORDER BY rank DESC, isbn
+        LIMIT $2
+        "#,
+    )
+    .bind(tsquery)
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
+
+/// Turn free text into a `tsquery` where every word is matched as a prefix, e.g. `gam thro` => `gam:* & thro:*`.
+/// Only alphanumeric characters are kept so user input can never produce tsquery syntax errors.
+fn prefix_tsquery(text: &str) -> Option<String> {
+    let terms: Vec<String> = search_terms(text)
+        .iter()
+        .map(|term| format!("{term}:*"))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" & "))
+    }
+}
+
+/// The lowercased alphanumeric words of a search text, each has to match the start of a word in the book
+pub fn search_terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Partial update of a book, `None` fields are left untouched.
+/// `metadata: Some(None)` clears the metadata column.
+#[derive(Debug, Default)]
+pub struct BookPatch {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub metadata: Option<Option<Metadata>>,
+}
+
+/// Insert a new book and return the stored row.
+/// Fails with a unique violation on `book_isbn_idx` if the isbn already exists.
+pub async fn create_book(executor: impl PgExecutor<'_>, book: &Book) -> Result<Book, sqlx::Error> {
+    sqlx::query_as::<_, Book>(
+        r#"
+        INSERT INTO book (title, author, isbn, metadata) VALUES ($1, $2, $3, $4)
+        RETURNING title, author, isbn, metadata
+        "#,
+    )
+    .bind(&book.title)
+    .bind(&book.author)
+    .bind(&book.isbn)
+    .bind(book.metadata.as_ref().map(Json))
+    .fetch_one(executor)
+    .await
+}
+
+/// Apply a partial update to the book with the given isbn.
+/// Returns `None` if there is no such book.
+pub async fn update_book(
+    executor: impl PgExecutor<'_>,
+    isbn: &Isbn,
+    patch: &BookPatch,
+) -> Result<Option<Book>, sqlx::Error> {
+    sqlx::query_as::<_, Book>(
+        r#"
+        UPDATE book SET
+            title = COALESCE($1, title),
+            author = COALESCE($2, author),
+            metadata = CASE WHEN $3 THEN $4 ELSE metadata END
+        WHERE isbn = $5
+        RETURNING title, author, isbn, metadata
+        "#,
+    )
+    .bind(&patch.title)
+    .bind(&patch.author)
+    .bind(patch.metadata.is_some())
+    .bind(patch.metadata.as_ref().and_then(|m| m.as_ref()).map(Json))
+    .bind(isbn)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Delete the book with the given isbn, returns whether a row was deleted.
+pub async fn delete_book(executor: impl PgExecutor<'_>, isbn: &Isbn) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM book WHERE isbn = $1")
+        .bind(isbn)
+        .execute(executor)
         .await?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
+}
+
+/// Number of rows written by `upsert_books`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UpsertCount {
+    pub inserted: u64,
+    pub updated: u64,
+}
+
+/// Rows per `INSERT`, each book binds 4 parameters and Postgres allows 65535
+const UPSERT_BATCH_SIZE: usize = 1000;
+
+/// Insert the books, or overwrite title, author and metadata of the ones whose isbn already exists.
+/// The isbns have to be unique within `books`.
+pub async fn upsert_books(pool: &sqlx::PgPool, books: &[Book]) -> Result<UpsertCount, sqlx::Error> {
+    let mut count = UpsertCount::default();
+    let mut tx = pool.begin().await?;
+
+    for batch in books.chunks(UPSERT_BATCH_SIZE) {
+        let mut query =
+            QueryBuilder::<Postgres>::new("INSERT INTO book (title, author, isbn, metadata) ");
+        query.push_values(batch, |mut row, book| {
+            row.push_bind(&book.title)
+                .push_bind(&book.author)
+                .push_bind(&book.isbn)
+                .push_bind(book.metadata.as_ref().map(Json));
+        });
+        // xmax is only set on rows which existed before the statement
+        query.push(
+            " ON CONFLICT (isbn) DO UPDATE SET \
+             title = EXCLUDED.title, author = EXCLUDED.author, metadata = EXCLUDED.metadata \
+             RETURNING xmax = 0 AS inserted",
+        );
+
+        let inserted: Vec<bool> = query.build_query_scalar().fetch_all(&mut *tx).await?;
+        for inserted in inserted {
+            match inserted {
+                true => count.inserted += 1,
+                false => count.updated += 1,
+            }
+        }
+    }
+
+    tx.commit().await?;
+    Ok(count)
 }
 
 /// Example show how to update records
 /// cargo run -- sqlx bookstore update
 pub async fn update_book_example(pool: &sqlx::PgPool) -> Result<(), Box<dyn Error>> {
-    let query = "update book set title = $1, author = $2, metadata = $3 where isbn = $4";
-    sqlx::query(query)
-        .bind("book01_changed".to_string())
-        .bind("fox new name".to_string())
-        .bind(
-            serde_json::to_value(Some(Metadata {
-                avg_review: 7.0,
-                tags: vec!["art".to_string()],
-            }))
-            .unwrap(),
-        )
-        .bind("000-111-222-33".to_string())
-        .execute(pool)
-        .await?;
+    let patch = BookPatch {
+        title: Some("book01_changed".to_string()),
+        author: Some("fox new name".to_string()),
+        metadata: Some(Some(Metadata {
+            avg_review: 7.0,
+            tags: vec!["art".to_string()],
+        })),
+    };
+    update_book(pool, &"978-1-111-22233-8".parse()?, &patch).await?;
 
-    let query = "update book set author = $1 where isbn = $2";
-    sqlx::query(query)
-        .bind("Margin games".to_string())
-        .bind("111-222-333-444".to_string())
-        .execute(pool)
-        .await?;
+    let patch = BookPatch {
+        author: Some("Margin games".to_string()),
+        ..Default::default()
+    };
+    update_book(pool, &"0-553-10354-7".parse()?, &patch).await?;
 
     Ok(())
 }
@@ -208,7 +525,7 @@ pub async fn transaction(pool: &sqlx::PgPool) -> Result<(), Box<dyn std::error::
     .execute(pool)
     .await?;
 
-    explicit_rollback_example(&pool, test_id).await?;
+    explicit_rollback_example(pool, test_id).await?;
 
     // check that inserted todo is not visible outside the transaction after explicit rollback
     let inserted_todo = sqlx::query!(
@@ -222,7 +539,7 @@ pub async fn transaction(pool: &sqlx::PgPool) -> Result<(), Box<dyn std::error::
 
     assert!(inserted_todo.is_err());
 
-    implicit_rollback_example(&pool, test_id).await?;
+    implicit_rollback_example(pool, test_id).await?;
 
     // check that inserted todo is not visible outside the transaction after implicit rollback
     let inserted_todo = sqlx::query!(
@@ -0,0 +1,323 @@
+use crate::command_line::CatalogFormat;
+use crate::db::bookstore::Book;
+use crate::db::seed::{BookCsvRow, BookFixture};
+use crate::isbn::Isbn;
+use futures::stream::StreamExt;
+use serde::Serialize;
+use sqlx::postgres::PgCopyIn;
+use sqlx::PgConnection;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Bytes of accepted rows buffered before they are sent to `COPY`
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A line of an import which was not loaded
+#[derive(Debug, Serialize)]
+pub struct Rejection {
+    pub line: u64,
+    pub reason: String,
+    /// The rejected line as it was read
+    pub record: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub inserted: i64,
+    pub updated: i64,
+    pub rejected: Vec<Rejection>,
+}
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Io(e) => write!(f, "{e}"),
+            CatalogError::Csv(e) => write!(f, "{e}"),
+            CatalogError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl From<std::io::Error> for CatalogError {
+    fn from(e: std::io::Error) -> Self {
+        CatalogError::Io(e)
+    }
+}
+
+impl From<sqlx::Error> for CatalogError {
+    fn from(e: sqlx::Error) -> Self {
+        CatalogError::Database(e)
+    }
+}
+
+/// A line of the input, parsed or with the reason it could not be
+struct InputRow {
+    line: u64,
+    record: String,
+    book: Result<BookFixture, String>,
+}
+
+/// Load books from `input` and upsert them on isbn in a single transaction.
+/// Rows are checked one by one, invalid ones are reported in `ImportReport::rejected` and the others
+/// are streamed with `COPY` into a staging table, so one bad line does not abort the import.
+/// CSV input has the `title,author,isbn,avg_review,tags` columns of the seed fixtures,
+/// JSON lines input has one `{"title", "author", "isbn", "metadata"}` object per line.
+pub async fn import_books(
+    pool: &sqlx::PgPool,
+    input: impl Read + 'static,
+    format: CatalogFormat,
+) -> Result<ImportReport, CatalogError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "CREATE TEMPORARY TABLE book_import \
+         (title TEXT, author TEXT, isbn TEXT, metadata JSONB) ON COMMIT DROP",
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut report = ImportReport::default();
+    let mut copy = tx
+        .copy_in_raw(
+            "COPY book_import (title, author, isbn, metadata) FROM STDIN WITH (FORMAT csv)",
+        )
+        .await?;
+    match copy_rows(&mut copy, read_rows(input, format)?, &mut report.rejected).await {
+        Ok(()) => {
+            copy.finish().await?;
+        }
+        Err(e) => {
+            copy.abort(e.to_string()).await?;
+            return Err(e);
+        }
+    }
+
+    // xmax is only set on rows which existed before the statement
+    let (inserted, updated): (i64, i64) = sqlx::query_as(
+        r#"
+        WITH upserted AS (
+            INSERT INTO book (title, author, isbn, metadata)
+            SELECT title, author, isbn, metadata FROM book_import
+            ON CONFLICT (isbn) DO UPDATE SET
+                title = EXCLUDED.title, author = EXCLUDED.author, metadata = EXCLUDED.metadata
+            RETURNING xmax = 0 AS inserted
+        )
+        SELECT count(*) FILTER (WHERE inserted), count(*) FILTER (WHERE NOT inserted) FROM upserted
+        "#,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    report.inserted = inserted;
+    report.updated = updated;
+    Ok(report)
+}
+
+/// Validate every row and send the accepted ones to `copy` as CSV
+async fn copy_rows(
+    copy: &mut PgCopyIn<&mut PgConnection>,
+    rows: impl Iterator<Item = Result<InputRow, CatalogError>>,
+    rejected: &mut Vec<Rejection>,
+) -> Result<(), CatalogError> {
+    let new_writer = || csv::Writer::from_writer(Vec::with_capacity(COPY_CHUNK_SIZE));
+    let mut writer = new_writer();
+    // Line on which each isbn was first seen, `ON CONFLICT` cannot update a row twice
+    let mut seen: HashMap<Isbn, u64> = HashMap::new();
+
+    for row in rows {
+        let InputRow { line, record, book } = row?;
+        let book = book
+            .and_then(validate)
+            .and_then(|book| match seen.entry(book.isbn.clone()) {
+                Entry::Occupied(first) => Err(format!(
+                    "isbn {} is already on line {}",
+                    book.isbn,
+                    first.get()
+                )),
+                Entry::Vacant(entry) => {
+                    entry.insert(line);
+                    Ok(book)
+                }
+            });
+        let book = match book {
+            Ok(book) => book,
+            Err(reason) => {
+                rejected.push(Rejection {
+                    line,
+                    reason,
+                    record,
+                });
+                continue;
+            }
+        };
+
+        let metadata = match &book.metadata {
+            Some(metadata) => serde_json::to_string(metadata).expect("metadata is serializable"),
+            // An unquoted empty field is NULL
+            None => String::new(),
+        };
+        writer
+            .write_record([&book.title, &book.author, book.isbn.as_str(), &metadata])
+            .map_err(CatalogError::Csv)?;
+        if writer.get_ref().len() >= COPY_CHUNK_SIZE {
+            let chunk = writer.into_inner().map_err(|e| e.into_error())?;
+            copy.send(chunk).await?;
+            writer = new_writer();
+        }
+    }
+
+    let chunk = writer.into_inner().map_err(|e| e.into_error())?;
+    copy.send(chunk).await?;
+    Ok(())
+}
+
+/// Check a row and trim its names, the isbn was validated when it was parsed
+fn validate(mut book: BookFixture) -> Result<BookFixture, String> {
+    book.title = book.title.trim().to_string();
+    book.author = book.author.trim().to_string();
+    if book.title.is_empty() {
+        return Err("title is empty".to_string());
+    }
+    if book.author.is_empty() {
+        return Err("author is empty".to_string());
+    }
+
+    if let Some(metadata) = &mut book.metadata {
+        for tag in &mut metadata.tags {
+            *tag = tag.trim().to_string();
+        }
+        metadata.validate()?;
+    }
+
+    Ok(book)
+}
+
+/// Parse the input lazily, a row which cannot be parsed is returned with the reason
+fn read_rows(
+    input: impl Read + 'static,
+    format: CatalogFormat,
+) -> Result<Box<dyn Iterator<Item = Result<InputRow, CatalogError>>>, CatalogError> {
+    match format {
+        CatalogFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input);
+            let headers = reader.headers().map_err(CatalogError::Csv)?.clone();
+
+            Ok(Box::new(reader.into_records().map(move |record| {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) if e.is_io_error() => return Err(CatalogError::Csv(e)),
+                    Err(e) => {
+                        return Ok(InputRow {
+                            line: e.position().map_or(0, |p| p.line()),
+                            record: String::new(),
+                            book: Err(e.to_string()),
+                        })
+                    }
+                };
+                let book = record
+                    .deserialize::<BookCsvRow>(Some(&headers))
+                    .map_err(|e| e.to_string())
+                    .and_then(|row| row.into_fixture().map_err(|e| e.to_string()));
+
+                Ok(InputRow {
+                    line: record.position().map_or(0, |p| p.line()),
+                    record: csv_line(&record),
+                    book,
+                })
+            })))
+        }
+        CatalogFormat::Jsonl => Ok(Box::new(
+            BufReader::new(input)
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|(index, line)| {
+                    let line_number = index as u64 + 1;
+                    let line = line?;
+                    Ok(InputRow {
+                        line: line_number,
+                        book: serde_json::from_str::<BookFixture>(&line).map_err(|e| e.to_string()),
+                        record: line,
+                    })
+                }),
+        )),
+    }
+}
+
+fn csv_line(record: &csv::StringRecord) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    let _ = writer.write_record(record);
+    let line = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&line).trim_end().to_string()
+}
+
+/// Write the rejected lines as CSV with the `line,reason,record` columns
+pub fn write_rejections(path: &Path, rejected: &[Rejection]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for rejection in rejected {
+        writer.serialize(rejection)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Stream every book ordered by isbn in the format `import_books` reads, CSV with `COPY ... TO STDOUT`
+/// and JSON lines serialized one `Book` at a time. Returns the number of books written.
+pub async fn export_books(
+    pool: &sqlx::PgPool,
+    mut output: impl Write,
+    format: CatalogFormat,
+) -> Result<u64, CatalogError> {
+    let mut rows: u64 = 0;
+    match format {
+        CatalogFormat::Csv => {
+            let statement = r#"
+            COPY (
+                SELECT title, author, isbn,
+                    metadata ->> 'avg_review' AS avg_review,
+                    array_to_string(
+                        ARRAY(SELECT jsonb_array_elements_text(metadata -> 'tags')), '|'
+                    ) AS tags
+                FROM book ORDER BY isbn
+            ) TO STDOUT WITH (FORMAT csv, HEADER)
+            "#;
+            let mut conn = pool.acquire().await?;
+            let mut stream = conn.copy_out_raw(statement).await?;
+            // Postgres sends one message per row, the header included
+            while let Some(chunk) = stream.next().await {
+                output.write_all(&chunk?)?;
+                rows += 1;
+            }
+            rows = rows.saturating_sub(1);
+        }
+        CatalogFormat::Jsonl => {
+            let mut books = sqlx::query_as::<_, Book>(
+                "SELECT title, author, isbn, metadata FROM book ORDER BY isbn",
+            )
+            .fetch(pool);
+            while let Some(book) = books.next().await {
+                serde_json::to_writer(&mut output, &book?).map_err(std::io::Error::from)?;
+                output.write_all(b"\n")?;
+                rows += 1;
+            }
+        }
+    }
+    output.flush()?;
+
+    Ok(rows)
+}
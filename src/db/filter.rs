@@ -0,0 +1,174 @@
+use crate::db::bookstore::Book;
+use async_graphql::InputObject;
+use sqlx::types::Json;
+use sqlx::{Postgres, QueryBuilder};
+
+/// Most conditions a filter may hold, nested filters included, which keeps the bind parameters of
+/// `push_book_filter` far below the 65535 Postgres accepts
+pub const MAX_FILTER_TERMS: usize = 100;
+
+/// Deepest nesting of `and` and `or` filters
+pub const MAX_FILTER_DEPTH: usize = 5;
+
+/// Filter over the bookstore `book` table.
+/// All fields set on one filter must match, `and` and `or` combine nested filters.
+/// An empty filter matches every book.
+#[derive(Debug, Clone, Default, InputObject)]
+pub struct BookFilter {
+    /// Books tagged with at least one of these tags
+    pub tags_any: Option<Vec<String>>,
+    /// Books tagged with all of these tags
+    pub tags_all: Option<Vec<String>>,
+    pub min_avg_review: Option<f64>,
+    pub max_avg_review: Option<f64>,
+    /// Case insensitive substring of the author
+    pub author_contains: Option<String>,
+    /// Case insensitive substring of the title
+    pub title_contains: Option<String>,
+    /// Every one of these filters must match
+    pub and: Option<Vec<BookFilter>>,
+    /// At least one of these filters must match
+    pub or: Option<Vec<BookFilter>>,
+}
+
+impl BookFilter {
+    /// Number of conditions set on the filter and its nested filters, an empty filter has none
+    pub fn terms(&self) -> usize {
+        let own = [
+            self.tags_any.is_some(),
+            self.tags_all.is_some(),
+            self.min_avg_review.is_some(),
+            self.max_avg_review.is_some(),
+            self.author_contains.is_some(),
+            self.title_contains.is_some(),
+        ]
+        .into_iter()
+        .filter(|set| *set)
+        .count();
+
+        own + self.nested().map(BookFilter::terms).sum::<usize>()
+    }
+
+    /// Why the filter is too large to run, see `MAX_FILTER_TERMS` and `MAX_FILTER_DEPTH`
+    pub fn validate(&self) -> Result<(), String> {
+        if self.depth() > MAX_FILTER_DEPTH {
+            return Err(format!(
+                "filters can be nested at most {MAX_FILTER_DEPTH} levels deep"
+            ));
+        }
+        let terms = self.terms();
+        if terms > MAX_FILTER_TERMS {
+            return Err(format!(
+                "a filter can hold at most {MAX_FILTER_TERMS} conditions, not {terms}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn depth(&self) -> usize {
+        1 + self.nested().map(BookFilter::depth).max().unwrap_or(0)
+    }
+
+    fn nested(&self) -> impl Iterator<Item = &BookFilter> {
+        self.and.iter().chain(&self.or).flatten()
+    }
+
+    /// Evaluate the filter on a book in memory, with the meaning of the SQL built by `push_book_filter`
+    pub fn matches(&self, book: &Book) -> bool {
+        let tags = book.metadata.as_ref().map(|metadata| &metadata.tags);
+        let avg_review = book
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.avg_review as f64);
+        let contains =
+            |value: &str, part: &str| value.to_lowercase().contains(&part.to_lowercase());
+
+        self.tags_any
+            .as_ref()
+            .is_none_or(|any| tags.is_some_and(|tags| any.iter().any(|tag| tags.contains(tag))))
+            && self
+                .tags_all
+                .as_ref()
+                .is_none_or(|all| tags.is_some_and(|tags| all.iter().all(|tag| tags.contains(tag))))
+            && self
+                .min_avg_review
+                .is_none_or(|min| avg_review.is_some_and(|avg| avg >= min))
+            && self
+                .max_avg_review
+                .is_none_or(|max| avg_review.is_some_and(|avg| avg <= max))
+            && self
+                .author_contains
+                .as_ref()
+                .is_none_or(|author| contains(&book.author, author))
+            && self
+                .title_contains
+                .as_ref()
+                .is_none_or(|title| contains(&book.title, title))
+            && self.and.iter().flatten().all(|nested| nested.matches(book))
+            && self
+                .or
+                .as_ref()
+                .is_none_or(|alternatives| alternatives.iter().any(|nested| nested.matches(book)))
+    }
+}
+
+/// Append the filter as a parenthesized boolean SQL expression, every value is bound as a parameter.
+/// The metadata expressions match the indexes created in `0005_book_metadata_indexes.sql`.
+pub fn push_book_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &BookFilter) {
+    query.push("(TRUE");
+
+    if let Some(tags) = &filter.tags_any {
+        query
+            .push(" AND metadata -> 'tags' ?| ")
+            .push_bind(tags.clone())
+            .push("::text[]");
+    }
+    if let Some(tags) = &filter.tags_all {
+        query
+            .push(" AND metadata -> 'tags' @> ")
+            .push_bind(Json(tags.clone()));
+    }
+    if let Some(min) = filter.min_avg_review {
+        query
+            .push(" AND ((metadata ->> 'avg_review')::float8) >= ")
+            .push_bind(min);
+    }
+    if let Some(max) = filter.max_avg_review {
+        query
+            .push(" AND ((metadata ->> 'avg_review')::float8) <= ")
+            .push_bind(max);
+    }
+    if let Some(author) = &filter.author_contains {
+        query
+            .push(" AND author ILIKE ")
+            .push_bind(contains_pattern(author));
+    }
+    if let Some(title) = &filter.title_contains {
+        query
+            .push(" AND title ILIKE ")
+            .push_bind(contains_pattern(title));
+    }
+    for nested in filter.and.iter().flatten() {
+        query.push(" AND ");
+        push_book_filter(query, nested);
+    }
+    if let Some(alternatives) = &filter.or {
+        query.push(" AND (FALSE");
+        for nested in alternatives {
+            query.push(" OR ");
+            push_book_filter(query, nested);
+        }
+        query.push(")");
+    }
+
+    query.push(")");
+}
+
+/// `ILIKE` pattern matching `value` anywhere, with the wildcards inside `value` escaped
+fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
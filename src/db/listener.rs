@@ -0,0 +1,103 @@
+use crate::events::{BookChanged, Broker, MutationType, TodoChanged};
+use crate::isbn::Isbn;
+use serde::Deserialize;
+use sqlx::postgres::{PgListener, PgNotification};
+use std::convert::Infallible;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Channels notified by the triggers in `0004_notify_changes.sql`
+const BOOK_CHANNEL: &str = "book_changed";
+const TODO_CHANNEL: &str = "todo_changed";
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+struct BookPayload {
+    op: String,
+    isbn: Isbn,
+}
+
+#[derive(Deserialize)]
+struct TodoPayload {
+    op: String,
+    id: i64,
+}
+
+/// Forward Postgres notifications about book and todo writes into the `Broker`,
+/// so subscribers of every instance see changes no matter which instance (or CLI) made them.
+/// Runs until the process exits, reconnecting with exponential backoff whenever the listener connection drops.
+/// Takes the `db::listener_pool`, not the repository pool, as the listener never returns its connection.
+pub async fn forward_notifications(pool: sqlx::PgPool, broker: Broker) {
+    let mut delay = MIN_RECONNECT_DELAY;
+
+    loop {
+        let Err(e) = listen(&pool, &broker, &mut delay).await;
+        error!(
+            "database listener failed: {}, reconnecting in {:?}",
+            e, delay
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Only returns when the listener connection could not be (re-)established
+async fn listen(
+    pool: &sqlx::PgPool,
+    broker: &Broker,
+    delay: &mut Duration,
+) -> Result<Infallible, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen_all([BOOK_CHANNEL, TODO_CHANNEL]).await?;
+    info!("listening for notifications on {BOOK_CHANNEL}, {TODO_CHANNEL}");
+    *delay = MIN_RECONNECT_DELAY;
+
+    loop {
+        // `try_recv` yields `None` once when the connection is lost, the next call reconnects
+        // and re-subscribes. Notifications sent while disconnected are lost.
+        match listener.try_recv().await? {
+            Some(notification) => dispatch(broker, &notification),
+            None => warn!("database listener connection lost, reconnecting"),
+        }
+    }
+}
+
+fn dispatch(broker: &Broker, notification: &PgNotification) {
+    let payload = notification.payload();
+    match notification.channel() {
+        BOOK_CHANNEL => match serde_json::from_str::<BookPayload>(payload) {
+            Ok(p) => {
+                if let Some(mutation_type) = mutation_type(&p.op) {
+                    broker.publish_book(BookChanged {
+                        mutation_type,
+                        isbn: p.isbn,
+                    });
+                }
+            }
+            Err(e) => warn!("invalid {BOOK_CHANNEL} payload {payload}: {e}"),
+        },
+        TODO_CHANNEL => match serde_json::from_str::<TodoPayload>(payload) {
+            Ok(p) => {
+                if let Some(mutation_type) = mutation_type(&p.op) {
+                    broker.publish_todo(TodoChanged {
+                        mutation_type,
+                        id: p.id,
+                    });
+                }
+            }
+            Err(e) => warn!("invalid {TODO_CHANNEL} payload {payload}: {e}"),
+        },
+        channel => warn!("notification on unexpected channel {channel}"),
+    }
+}
+
+fn mutation_type(op: &str) -> Option<MutationType> {
+    match op {
+        "INSERT" => Some(MutationType::Created),
+        "UPDATE" => Some(MutationType::Updated),
+        "DELETE" => Some(MutationType::Deleted),
+        _ => None,
+    }
+}
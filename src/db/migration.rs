@@ -0,0 +1,279 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::postgres::{PgConnectOptions, PgConnection};
+use sqlx::{Connection, PgPool};
+use std::str::FromStr;
+use tracing::info;
+
+/// The bookstore migrations embedded in the binary, each `.up.sql` has a matching `.down.sql`
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/bookstore");
+
+/// Key of the advisory lock taken by `migrate_exclusively`, "bookstor" in ASCII
+const MIGRATION_LOCK_KEY: i64 = 0x626f_6f6b_7374_6f72;
+
+/// How the migrations recorded in `_sqlx_migrations` compare to the embedded ones
+#[derive(Serialize, Debug, Default)]
+pub struct MigrationStatus {
+    /// Latest successfully applied version
+    pub current: Option<i64>,
+    /// Latest embedded version
+    pub expected: Option<i64>,
+    /// Embedded migrations which have not been applied
+    pub pending: Vec<i64>,
+    /// Applied migrations whose file has been edited since
+    pub modified: Vec<i64>,
+    /// Applied migrations which are not embedded, the database is ahead of this binary
+    pub unknown: Vec<i64>,
+    /// Migrations which failed half way, embedded or not
+    pub failed: Vec<i64>,
+}
+
+impl MigrationStatus {
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty()
+            && self.modified.is_empty()
+            && self.unknown.is_empty()
+            && self.failed.is_empty()
+    }
+
+    /// Like `is_up_to_date` but tolerates `unknown` migrations, the database may already
+    /// carry the schema of a newer release while this one is still being rolled out.
+    pub fn is_compatible(&self) -> bool {
+        self.pending.is_empty() && self.modified.is_empty() && self.failed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    Modified,
+    Failed,
+    Unknown,
+}
+
+impl std::fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Modified => "modified",
+            MigrationState::Failed => "failed",
+            MigrationState::Unknown => "unknown",
+        })
+    }
+}
+
+/// One migration, embedded, applied or both
+#[derive(Debug)]
+pub struct MigrationEntry {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+    /// Hex sha384 of the migration, the recorded one for applied migrations
+    pub checksum: String,
+    pub installed_on: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AppliedMigration {
+    version: i64,
+    description: String,
+    checksum: Vec<u8>,
+    success: bool,
+    installed_on: DateTime<Utc>,
+}
+
+async fn applied_migrations(pool: &PgPool) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    let table_exists: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    match table_exists {
+        true => {
+            sqlx::query_as(
+                "SELECT version, description, checksum, success, installed_on \
+                 FROM _sqlx_migrations ORDER BY version",
+            )
+            .fetch_all(pool)
+            .await
+        }
+        false => Ok(Vec::new()),
+    }
+}
+
+/// Every embedded and applied migration ordered by version
+pub async fn list_migrations(pool: &PgPool) -> Result<Vec<MigrationEntry>, sqlx::Error> {
+    let applied = applied_migrations(pool).await?;
+
+    let mut entries: Vec<MigrationEntry> = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(
+            |migration| match applied.iter().find(|a| a.version == migration.version) {
+                None => MigrationEntry {
+                    version: migration.version,
+                    description: migration.description.to_string(),
+                    state: MigrationState::Pending,
+                    checksum: hex(&migration.checksum),
+                    installed_on: None,
+                },
+                Some(applied) => MigrationEntry {
+                    version: migration.version,
+                    description: migration.description.to_string(),
+                    state: if !applied.success {
+                        MigrationState::Failed
+                    } else if *applied.checksum != *migration.checksum {
+                        MigrationState::Modified
+                    } else {
+                        MigrationState::Applied
+                    },
+                    checksum: hex(&applied.checksum),
+                    installed_on: Some(applied.installed_on),
+                },
+            },
+        )
+        .collect();
+    entries.extend(
+        applied
+            .iter()
+            .filter(|a| !MIGRATOR.version_exists(a.version))
+            .map(|applied| MigrationEntry {
+                version: applied.version,
+                description: applied.description.clone(),
+                state: if applied.success {
+                    MigrationState::Unknown
+                } else {
+                    MigrationState::Failed
+                },
+                checksum: hex(&applied.checksum),
+                installed_on: Some(applied.installed_on),
+            }),
+    );
+    entries.sort_by_key(|entry| entry.version);
+
+    Ok(entries)
+}
+
+pub async fn migration_status(pool: &PgPool) -> Result<MigrationStatus, sqlx::Error> {
+    let entries = list_migrations(pool).await?;
+    let versions = |state| {
+        entries
+            .iter()
+            .filter(|entry| entry.state == state)
+            .map(|entry| entry.version)
+            .collect::<Vec<_>>()
+    };
+
+    Ok(MigrationStatus {
+        current: entries
+            .iter()
+            .filter(|entry| entry.installed_on.is_some() && entry.state != MigrationState::Failed)
+            .map(|entry| entry.version)
+            .max(),
+        expected: MIGRATOR.iter().map(|m| m.version).max(),
+        pending: versions(MigrationState::Pending),
+        modified: versions(MigrationState::Modified),
+        unknown: versions(MigrationState::Unknown),
+        failed: versions(MigrationState::Failed),
+    })
+}
+
+/// Apply the pending migrations, returns their versions.
+/// With `dry_run` nothing is applied and the versions which would be are returned.
+pub async fn migrate_up(pool: &PgPool, dry_run: bool) -> Result<Vec<i64>, MigrateError> {
+    let pending = migration_status(pool).await?.pending;
+    if !dry_run {
+        MIGRATOR.run(pool).await?;
+    }
+    Ok(pending)
+}
+
+/// Apply the pending migrations while holding an advisory lock, so when several instances start at
+/// once one of them migrates and the others wait for it, then find nothing left to apply.
+/// The lock lives on its own connection and is released when it closes, even if migrating fails.
+pub async fn migrate_exclusively(pool: &PgPool) -> Result<Vec<i64>, MigrateError> {
+    let mut lock = PgConnection::connect_with(&pool.connect_options()).await?;
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .fetch_one(&mut lock)
+        .await?;
+    if !locked {
+        info!("Another instance is migrating the database, waiting for it to finish");
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut lock)
+            .await?;
+    }
+
+    let applied = migrate_up(pool, false).await;
+    lock.close().await?;
+    applied
+}
+
+/// Revert the applied migrations above `target`, the latest one only when `target` is `None`.
+/// Returns the reverted versions, newest first, or the ones which would be with `dry_run`.
+pub async fn migrate_down(
+    pool: &PgPool,
+    target: Option<i64>,
+    dry_run: bool,
+) -> Result<Vec<i64>, MigrateError> {
+    let mut applied: Vec<i64> = applied_migrations(pool)
+        .await?
+        .iter()
+        .map(|a| a.version)
+        .collect();
+    applied.reverse();
+
+    let target = target.unwrap_or_else(|| applied.get(1).copied().unwrap_or(0));
+    let reverted: Vec<i64> = applied.into_iter().filter(|v| *v > target).collect();
+    if !dry_run && !reverted.is_empty() {
+        MIGRATOR.undo(pool, target).await?;
+    }
+    Ok(reverted)
+}
+
+/// Drop every table of the `public` schema and migrate from scratch.
+/// Refuses to run unless `url` points at this machine.
+pub async fn reset(pool: &PgPool, url: &str) -> Result<(), MigrateError> {
+    let options = PgConnectOptions::from_str(url)?;
+    let host = options.get_host();
+    if !is_local_host(host) {
+        return Err(MigrateError::Source(
+            format!("refusing to reset the database on {host}, only local databases can be reset")
+                .into(),
+        ));
+    }
+
+    delete_all_tables(pool).await?;
+    MIGRATOR.run(pool).await
+}
+
+fn is_local_host(host: &str) -> bool {
+    // Unix domain sockets are given as a directory
+    host.starts_with('/') || matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+async fn delete_all_tables(pool: &PgPool) -> Result<(), sqlx::Error> {
+    // sqlx::PgPool already specify the db
+    let sql = r#"
+        DO $$ DECLARE
+            r RECORD;
+        BEGIN
+            -- Iterate over all tables in the public schema
+            FOR r IN (SELECT tablename FROM pg_tables WHERE schemaname = 'public') LOOP
+                -- Drop each table
+                EXECUTE 'DROP TABLE IF EXISTS ' || quote_ident(r.tablename) || ' CASCADE';
+            END LOOP;
+        END $$;
+    "#;
+
+    sqlx::query(sql).execute(pool).await?;
+
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,48 @@
+use sqlx::{PgConnection, PgExecutor};
+
+/// Look up the query registered under the given sha256 hash
+pub async fn fetch_persisted_query(
+    executor: impl PgExecutor<'_>,
+    sha256_hash: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT query FROM persisted_queries WHERE sha256_hash = $1")
+        .bind(sha256_hash)
+        .fetch_optional(executor)
+        .await
+}
+
+/// Register a query under its sha256 hash, registering the same hash twice is a no-op.
+/// Only the `max_stored` most recently registered queries are kept, older ones are deleted.
+pub async fn insert_persisted_query(
+    conn: &mut PgConnection,
+    sha256_hash: &str,
+    query: &str,
+    max_stored: i64,
+) -> Result<(), sqlx::Error> {
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO persisted_queries (sha256_hash, query) VALUES ($1, $2)
+        ON CONFLICT (sha256_hash) DO NOTHING
+        "#,
+    )
+    .bind(sha256_hash)
+    .bind(query)
+    .execute(&mut *conn)
+    .await?;
+
+    if inserted.rows_affected() > 0 {
+        sqlx::query(
+            r#"
+            DELETE FROM persisted_queries WHERE sha256_hash IN (
+                SELECT sha256_hash FROM persisted_queries
+                ORDER BY created_at DESC, sha256_hash OFFSET $1
+            )
+            "#,
+        )
+        .bind(max_stored)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
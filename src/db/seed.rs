@@ -0,0 +1,333 @@
+use crate::db::bookstore::{upsert_books, Book, Metadata, UpsertCount, TAG_SEPARATOR};
+use crate::db::todo::insert_todo_if_missing;
+use crate::isbn::{isbn13_check_digit, Isbn, IsbnError};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Books and todos of a fixture file.
+///
+/// JSON and YAML files hold both lists:
+/// ```yaml
+/// books:
+///   - title: A Game of Thrones
+///     author: Martin
+///     isbn: 0-553-10354-7
+///     metadata: { avg_review: 9.4, tags: [fantasy, epic] }
+/// todos:
+///   - description: Read it
+///     done: false
+/// ```
+/// A CSV file holds one of them, books when it has an `isbn` column (`title,author,isbn,avg_review,tags`
+/// with tags separated by `|`) and todos when it has a `description` column (`description,done`).
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    #[serde(default)]
+    pub books: Vec<BookFixture>,
+    #[serde(default)]
+    pub todos: Vec<TodoFixture>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BookFixture {
+    pub title: String,
+    pub author: String,
+    pub isbn: Isbn,
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TodoFixture {
+    pub description: String,
+    #[serde(default)]
+    pub done: bool,
+}
+
+/// A book in a CSV file, tags are separated by `|`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BookCsvRow {
+    title: String,
+    author: String,
+    isbn: String,
+    avg_review: Option<f32>,
+    tags: Option<String>,
+}
+
+impl BookCsvRow {
+    pub(crate) fn into_fixture(self) -> Result<BookFixture, FixtureError> {
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .flat_map(|tags| tags.split(TAG_SEPARATOR))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        let metadata = match (self.avg_review, tags.is_empty()) {
+            (Some(avg_review), _) => Some(Metadata { avg_review, tags }),
+            (None, true) => None,
+            (None, false) => return Err(FixtureError::TagsWithoutReview(self.isbn)),
+        };
+        let isbn = Isbn::parse(&self.isbn).map_err(|e| FixtureError::Isbn(self.isbn, e))?;
+
+        Ok(BookFixture {
+            title: self.title,
+            author: self.author,
+            isbn,
+            metadata,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TodoCsvRow {
+    description: String,
+    /// Empty cells are not done
+    done: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum FixtureError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    Csv(csv::Error),
+    /// The extension is not one of json, yaml, yml or csv
+    UnknownFormat,
+    /// A CSV file has neither an `isbn` nor a `description` column
+    UnknownCsvColumns,
+    /// A CSV book has tags but no `avg_review`
+    TagsWithoutReview(String),
+    Isbn(String, IsbnError),
+}
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixtureError::Io(e) => write!(f, "{e}"),
+            FixtureError::Json(e) => write!(f, "{e}"),
+            FixtureError::Yaml(e) => write!(f, "{e}"),
+            FixtureError::Csv(e) => write!(f, "{e}"),
+            FixtureError::UnknownFormat => {
+                write!(f, "unknown fixture format, use .json, .yaml, .yml or .csv")
+            }
+            FixtureError::UnknownCsvColumns => write!(
+                f,
+                "a CSV fixture needs an `isbn` column for books or a `description` column for todos"
+            ),
+            FixtureError::TagsWithoutReview(isbn) => {
+                write!(f, "book {isbn} has tags but no avg_review")
+            }
+            FixtureError::Isbn(isbn, e) => write!(f, "{e}: {isbn:?}"),
+        }
+    }
+}
+
+impl std::error::Error for FixtureError {}
+
+impl Fixture {
+    /// Read a fixture file, the format is taken from its extension
+    pub fn load(path: &Path) -> Result<Self, FixtureError> {
+        let content = std::fs::read_to_string(path).map_err(FixtureError::Io)?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => serde_json::from_str(&content).map_err(FixtureError::Json),
+            "yaml" | "yml" => serde_yaml::from_str(&content).map_err(FixtureError::Yaml),
+            "csv" => Self::from_csv(&content),
+            _ => Err(FixtureError::UnknownFormat),
+        }
+    }
+
+    fn from_csv(content: &str) -> Result<Self, FixtureError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes());
+        let headers = reader.headers().map_err(FixtureError::Csv)?.clone();
+        let mut fixture = Fixture::default();
+
+        if headers.iter().any(|h| h == "isbn") {
+            for row in reader.deserialize::<BookCsvRow>() {
+                let row = row.map_err(FixtureError::Csv)?;
+                fixture.books.push(row.into_fixture()?);
+            }
+        } else if headers.iter().any(|h| h == "description") {
+            for row in reader.deserialize::<TodoCsvRow>() {
+                let row = row.map_err(FixtureError::Csv)?;
+                fixture.todos.push(TodoFixture {
+                    description: row.description,
+                    done: row.done.unwrap_or(false),
+                });
+            }
+        } else {
+            return Err(FixtureError::UnknownCsvColumns);
+        }
+
+        Ok(fixture)
+    }
+
+    pub fn extend(&mut self, other: Fixture) {
+        self.books.extend(other.books);
+        self.todos.extend(other.todos);
+    }
+}
+
+/// What `seed` wrote
+#[derive(Debug, Default)]
+pub struct SeedReport {
+    pub books: UpsertCount,
+    pub todos_inserted: u64,
+    pub todos_existing: u64,
+}
+
+/// Upsert the books on isbn and insert the todos which do not exist yet, so seeding again is harmless.
+/// When an isbn appears several times the last book wins.
+pub async fn seed(pool: &sqlx::PgPool, fixture: Fixture) -> Result<SeedReport, sqlx::Error> {
+    let books: BTreeMap<Isbn, Book> = fixture
+        .books
+        .into_iter()
+        .map(|book| {
+            let book = Book {
+                title: book.title,
+                author: book.author,
+                isbn: book.isbn,
+                metadata: book.metadata,
+            };
+            (book.isbn.clone(), book)
+        })
+        .collect();
+    let books: Vec<Book> = books.into_values().collect();
+
+    let mut report = SeedReport {
+        books: upsert_books(pool, &books).await?,
+        ..SeedReport::default()
+    };
+    for todo in &fixture.todos {
+        match insert_todo_if_missing(pool, &todo.description, todo.done).await? {
+            true => report.todos_inserted += 1,
+            false => report.todos_existing += 1,
+        }
+    }
+
+    Ok(report)
+}
+
+const ADJECTIVES: &[&str] = &[
+    "Silent",
+    "Crimson",
+    "Hidden",
+    "Broken",
+    "Golden",
+    "Last",
+    "Distant",
+    "Burning",
+    "Frozen",
+    "Secret",
+    "Wandering",
+    "Forgotten",
+    "Endless",
+    "Hollow",
+    "Bright",
+    "Shattered",
+];
+const NOUNS: &[&str] = &[
+    "River",
+    "Kingdom",
+    "Garden",
+    "Empire",
+    "Lighthouse",
+    "Forest",
+    "Harbor",
+    "Mountain",
+    "Library",
+    "Storm",
+    "Voyage",
+    "Crown",
+    "Machine",
+    "Orchard",
+    "Citadel",
+    "Archive",
+];
+const FIRST_NAMES: &[&str] = &[
+    "Ada", "Boris", "Chloe", "Dmitri", "Elena", "Farid", "Greta", "Hiro", "Ines", "Jonas", "Kofi",
+    "Lena", "Mateo", "Nadia", "Oskar", "Priya",
+];
+const LAST_NAMES: &[&str] = &[
+    "Abbott", "Brandt", "Castillo", "Dubois", "Eriksen", "Fischer", "Garcia", "Haddad", "Ivanova",
+    "Jensen", "Kowalski", "Larsen", "Moreau", "Novak", "Okafor", "Petrov",
+];
+const TAGS: &[&str] = &[
+    "fantasy",
+    "epic",
+    "science-fiction",
+    "mystery",
+    "romance",
+    "history",
+    "poetry",
+    "thriller",
+    "biography",
+    "classic",
+    "adventure",
+    "horror",
+];
+
+/// `count` made up books for load and pagination testing.
+/// The same `seed` gives the same books, and the n-th book always gets the same isbn,
+/// so generating again updates the books instead of adding more.
+pub fn synthetic_books(count: usize, seed: u64) -> Vec<BookFixture> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    (0..count)
+        .map(|n| {
+            let pick = |rng: &mut StdRng, words: &[&str]| words.choose(rng).unwrap().to_string();
+            let title = format!(
+                "The {} {}",
+                pick(&mut rng, ADJECTIVES),
+                pick(&mut rng, NOUNS)
+            );
+            let author = format!(
+                "{} {}",
+                pick(&mut rng, FIRST_NAMES),
+                pick(&mut rng, LAST_NAMES)
+            );
+            // One book in ten has no metadata, like books created without it
+            let metadata = rng.gen_ratio(9, 10).then(|| {
+                let tag_count = rng.gen_range(0..=3);
+                Metadata {
+                    avg_review: (rng.gen_range(10..=100) as f32) / 10.0,
+                    tags: TAGS
+                        .choose_multiple(&mut rng, tag_count)
+                        .map(|tag| tag.to_string())
+                        .collect(),
+                }
+            });
+
+            BookFixture {
+                title,
+                author,
+                isbn: synthetic_isbn(n),
+                metadata,
+            }
+        })
+        .collect()
+}
+
+/// Start of every synthetic ISBN-13: the 979 Bookland prefix, registration group 9, which the
+/// International ISBN Agency has not allocated, and a fixed `9` block
+const SYNTHETIC_ISBN_PREFIX: &str = "97999";
+
+/// A valid ISBN-13 made of `n` under `SYNTHETIC_ISBN_PREFIX`. No publisher can hold an ISBN in an
+/// unallocated registration group, so generated books cannot collide with a real one.
+fn synthetic_isbn(n: usize) -> Isbn {
+    let body = format!("{SYNTHETIC_ISBN_PREFIX}{:07}", n % 10_000_000);
+    let check = isbn13_check_digit(&body);
+    Isbn::parse(&format!("{body}{check}")).expect("the check digit was computed")
+}
@@ -0,0 +1,134 @@
+use async_graphql::SimpleObject;
+use sqlx::PgExecutor;
+
+/// A row of the `todos` table created by `0002_todos.sql`.
+/// Queries here use the compile-time checked `sqlx::query!` macros,
+/// run `cargo sqlx prepare` after changing them to refresh the `.sqlx` offline data.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Todo {
+    pub id: i64,
+    pub description: String,
+    pub done: bool,
+}
+
+/// Fetch all todos ordered by id, optionally only those with the given `done` state
+pub async fn fetch_todos(
+    executor: impl PgExecutor<'_>,
+    done: Option<bool>,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT id, description, done FROM todos
+        WHERE $1::BOOLEAN IS NULL OR done = $1
+        ORDER BY id
+        "#,
+        done
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Fetch a single todo by id, `None` if there is no such todo
+pub async fn fetch_todo(
+    executor: impl PgExecutor<'_>,
+    id: i64,
+) -> Result<Option<Todo>, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT id, description, done FROM todos WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Insert a new todo which is not done yet
+pub async fn add_todo(
+    executor: impl PgExecutor<'_>,
+    description: &str,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"
+        INSERT INTO todos (description) VALUES ($1)
+        RETURNING id, description, done
+        "#,
+        description
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Insert a todo unless one with the same description exists, returns whether it was inserted.
+/// Todos have no natural key, the description stands in for one so seeding twice is harmless.
+pub async fn insert_todo_if_missing(
+    pool: &sqlx::PgPool,
+    description: &str,
+    done: bool,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO todos (description, done)
+        SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM todos WHERE description = $1)
+        "#,
+        description,
+        done
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Flip the `done` state of a todo, `None` if there is no such todo
+pub async fn toggle_todo(
+    executor: impl PgExecutor<'_>,
+    id: i64,
+) -> Result<Option<Todo>, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"
+        UPDATE todos SET done = NOT done WHERE id = $1
+        RETURNING id, description, done
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Change the description of a todo, `None` if there is no such todo
+pub async fn rename_todo(
+    executor: impl PgExecutor<'_>,
+    id: i64,
+    description: &str,
+) -> Result<Option<Todo>, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"
+        UPDATE todos SET description = $2 WHERE id = $1
+        RETURNING id, description, done
+        "#,
+        id,
+        description
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Delete a todo, returns whether a row was deleted
+pub async fn delete_todo(executor: impl PgExecutor<'_>, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM todos WHERE id = $1
+        "#,
+        id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
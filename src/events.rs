@@ -0,0 +1,80 @@
+use crate::isbn::Isbn;
+use async_graphql::Enum;
+use futures::stream::{self, Stream};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// How many events a slow subscriber may fall behind before it starts missing events
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum MutationType {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// A book was created, updated or deleted. Only the key travels with the event,
+/// subscribers load the current row themselves.
+#[derive(Clone, Debug)]
+pub(crate) struct BookChanged {
+    pub mutation_type: MutationType,
+    pub isbn: Isbn,
+}
+
+/// A todo was created, updated or deleted.
+#[derive(Clone, Debug)]
+pub(crate) struct TodoChanged {
+    pub mutation_type: MutationType,
+    pub id: i64,
+}
+
+/// In-process fan out of change events to GraphQL subscription streams.
+/// Cloning is cheap, all clones publish into the same channels.
+#[derive(Clone)]
+pub(crate) struct Broker {
+    books: broadcast::Sender<BookChanged>,
+    todos: broadcast::Sender<TodoChanged>,
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        Broker {
+            books: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            todos: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl Broker {
+    pub fn publish_book(&self, event: BookChanged) {
+        // An error only means nobody is subscribed right now
+        let _ = self.books.send(event);
+    }
+
+    pub fn publish_todo(&self, event: TodoChanged) {
+        let _ = self.todos.send(event);
+    }
+
+    pub fn book_events(&self) -> impl Stream<Item = BookChanged> {
+        into_stream(self.books.subscribe())
+    }
+
+    pub fn todo_events(&self) -> impl Stream<Item = TodoChanged> {
+        into_stream(self.todos.subscribe())
+    }
+}
+
+fn into_stream<T: Clone>(rx: broadcast::Receiver<T>) -> impl Stream<Item = T> {
+    stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("subscriber lagged behind, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
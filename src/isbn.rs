@@ -0,0 +1,250 @@
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use serde::{Deserialize, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres};
+use std::fmt;
+use std::str::FromStr;
+
+/// A valid ISBN, always held as the 13 digits of its ISBN-13 without hyphens.
+/// Parsing accepts ISBN-10 and ISBN-13 with or without hyphens, so equal books compare equal.
+/// Stored as text, the `isbn13` function of the migrations keeps the `book.isbn` column in the same form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Isbn(String);
+
+impl Isbn {
+    pub fn parse(input: &str) -> Result<Self, IsbnError> {
+        normalize_isbn(input).map(Isbn)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Isbn {
+    type Err = IsbnError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Isbn::parse(s)
+    }
+}
+
+impl TryFrom<String> for Isbn {
+    type Error = IsbnError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Isbn::parse(&s)
+    }
+}
+
+impl From<Isbn> for String {
+    fn from(isbn: Isbn) -> Self {
+        isbn.0
+    }
+}
+
+impl fmt::Display for Isbn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+/// An ISBN-10 or ISBN-13, hyphens are allowed in inputs. Always returned as the 13 digits of the ISBN-13.
+#[Scalar(
+    name = "Isbn",
+    specified_by_url = "https://www.isbn-international.org/content/what-isbn"
+)]
+impl ScalarType for Isbn {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(s) => Isbn::parse(s).map_err(InputValueError::custom),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn is_valid(value: &Value) -> bool {
+        matches!(value, Value::String(_))
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.clone())
+    }
+}
+
+impl sqlx::Type<Postgres> for Isbn {
+    fn type_info() -> PgTypeInfo {
+        <String as sqlx::Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as sqlx::Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl PgHasArrayType for Isbn {
+    fn array_type_info() -> PgTypeInfo {
+        <String as PgHasArrayType>::array_type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for Isbn {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <&str as Encode<Postgres>>::encode(self.as_str(), buf)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Isbn {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let isbn = <&str as Decode<Postgres>>::decode(value)?;
+        Ok(Isbn::parse(isbn)?)
+    }
+}
+
+/// Why a string is not an ISBN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IsbnError {
+    Character(char),
+    /// Number of digits once hyphens and spaces are removed, ISBNs have 10 or 13
+    Length(usize),
+    /// First three digits of an ISBN-13 which is not under the 978 or 979 Bookland prefixes
+    Prefix(String),
+    Checksum,
+}
+
+impl fmt::Display for IsbnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsbnError::Character(c) => write!(f, "invalid character {c:?} in ISBN"),
+            IsbnError::Length(length) => {
+                write!(f, "an ISBN has 10 or 13 digits, not {length}")
+            }
+            IsbnError::Prefix(prefix) => {
+                write!(f, "an ISBN-13 starts with 978 or 979, not {prefix}")
+            }
+            IsbnError::Checksum => write!(f, "wrong ISBN check digit"),
+        }
+    }
+}
+
+impl std::error::Error for IsbnError {}
+
+/// Validate an ISBN-10 or ISBN-13 and return it as the 13 digits of its ISBN-13.
+/// Hyphens and spaces are ignored, the check digit of an ISBN-10 may be `X`.
+/// Other EAN-13 than the 978 and 979 Bookland prefixes are rejected even with a valid check digit.
+pub fn normalize_isbn(input: &str) -> Result<String, IsbnError> {
+    let digits: String = input
+        .chars()
+        .filter(|c| *c != '-' && *c != ' ')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let last = digits.len().saturating_sub(1);
+    if let Some(c) = digits
+        .chars()
+        .enumerate()
+        .find(|(i, c)| !(c.is_ascii_digit() || (*c == 'X' && *i == last && last == 9)))
+        .map(|(_, c)| c)
+    {
+        return Err(IsbnError::Character(c));
+    }
+
+    match digits.len() {
+        10 => {
+            if isbn10_check_digit(&digits[..9]) != digits.chars().last().unwrap() {
+                return Err(IsbnError::Checksum);
+            }
+            let body = format!("978{}", &digits[..9]);
+            let check = isbn13_check_digit(&body);
+            Ok(format!("{body}{check}"))
+        }
+        13 => {
+            if !digits.starts_with("978") && !digits.starts_with("979") {
+                return Err(IsbnError::Prefix(digits[..3].to_string()));
+            }
+            if isbn13_check_digit(&digits[..12]) != digits.chars().last().unwrap() {
+                return Err(IsbnError::Checksum);
+            }
+            Ok(digits)
+        }
+        length => Err(IsbnError::Length(length)),
+    }
+}
+
+/// Check digit of the first 12 digits of an ISBN-13, weighted 1 and 3 alternately
+pub fn isbn13_check_digit(body: &str) -> char {
+    let sum: u32 = body
+        .bytes()
+        .enumerate()
+        .map(|(i, digit)| (digit - b'0') as u32 * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    char::from(b'0' + ((10 - sum % 10) % 10) as u8)
+}
+
+/// Check digit of the first 9 digits of an ISBN-10, weighted 10 down to 2, 10 is written `X`
+fn isbn10_check_digit(body: &str) -> char {
+    let sum: u32 = body
+        .bytes()
+        .enumerate()
+        .map(|(i, digit)| (digit - b'0') as u32 * (10 - i as u32))
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => 'X',
+        check => char::from(b'0' + check as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_to_isbn13() {
+        let cases = [
+            ("0553103547", "9780553103540"),
+            ("0-553-10354-7", "9780553103540"),
+            ("0 553 10354 7", "9780553103540"),
+            ("0-8044-2957-X", "9780804429573"),
+            ("0-8044-2957-x", "9780804429573"),
+            ("155404295X", "9781554042951"),
+            ("9780306406157", "9780306406157"),
+            ("978-0-306-40615-7", "9780306406157"),
+            (" 978 0 306 40615 7 ", "9780306406157"),
+            ("9791000000008", "9791000000008"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(normalize_isbn(input).as_deref(), Ok(expected), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_isbns() {
+        let cases = [
+            ("0-553-10354-8", IsbnError::Checksum),
+            ("0-8044-2957-9", IsbnError::Checksum),
+            ("978-0-306-40615-8", IsbnError::Checksum),
+            ("2000000000008", IsbnError::Prefix("200".to_string())),
+            ("4006381333931", IsbnError::Prefix("400".to_string())),
+            ("978030640615X", IsbnError::Character('X')),
+            ("X553103547", IsbnError::Character('X')),
+            ("0553_103547", IsbnError::Character('_')),
+            ("055310354", IsbnError::Length(9)),
+            ("97803064061570", IsbnError::Length(14)),
+            ("", IsbnError::Length(0)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(normalize_isbn(input), Err(expected), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn equal_books_compare_equal() {
+        assert_eq!(
+            Isbn::parse("0-8044-2957-X").unwrap(),
+            Isbn::parse("978-0-8044-2957-3").unwrap()
+        );
+    }
+}
@@ -1,27 +1,139 @@
+use crate::auth::{authenticate, generate_api_key, hash_api_key, Authenticator, JwtVerifier};
+use crate::command_line::ApiKeyAction;
 use crate::command_line::Arguments;
 use crate::command_line::BookstoreEx;
-use crate::command_line::MigrationFolder;
+use crate::command_line::CatalogAction;
+use crate::command_line::ConfigAction;
+use crate::command_line::MigrateAction;
+use crate::command_line::SchemaAction;
 use crate::command_line::SubCommand;
-use crate::model::QueryRoot;
-use crate::observability::metrics::{create_prometheus_recorder, track_metrics};
+use crate::command_line::TrustedDocumentsAction;
+use crate::config::{Config, PersistedQueryStoreKind, RepositoryKind};
+use crate::db::seed::{synthetic_books, Fixture};
+use crate::events::Broker;
+use crate::model::{
+    AuthorBooksLoader, BookLoader, BookRepository, DocumentMode, LruStore, MemoryRepository,
+    PostgresRepository, PostgresStore, Severity, TodoRepository, TrustedDocuments,
+};
+use crate::observability::metrics::{
+    create_prometheus_recorder, spawn_pool_metrics, track_metrics,
+};
 use crate::observability::tracing::setup_tracer;
-use crate::routes::{graphql_handler, graphql_playground, health};
-use async_graphql::{EmptyMutation, EmptySubscription, Schema};
+use crate::rate_limit::{rate_limit, RateLimiter};
+use crate::routes::{
+    graphql_get_handler, graphql_handler, graphql_ws_handler, liveness, readiness,
+};
+use async_graphql::dataloader::DataLoader;
 use axum::middleware;
 use axum::{extract::Extension, routing::get, Router, Server};
 use clap::Parser;
 use command_line::SqlCase;
 use dotenv::dotenv;
 use std::future::ready;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::signal;
-use tracing::info;
+use tracing::{error, info, warn};
 
+mod auth;
 mod command_line;
+mod config;
 mod db;
+mod events;
+mod isbn;
 mod model;
 mod observability;
+mod rate_limit;
 mod routes;
 
+async fn migrate(pool: &sqlx::PgPool, url: &str, action: MigrateAction) {
+    use db::migration::{self, MigrationState};
+
+    let fail = |e: &dyn std::fmt::Display| -> ! {
+        eprintln!("{e}");
+        std::process::exit(1)
+    };
+    let join = |versions: &[i64]| {
+        versions
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match action {
+        MigrateAction::Up { dry_run } => {
+            let applied = migration::migrate_up(pool, dry_run)
+                .await
+                .unwrap_or_else(|e| fail(&e));
+            match (applied.is_empty(), dry_run) {
+                (true, _) => println!("Already up to date"),
+                (false, true) => println!("Would apply {}", join(&applied)),
+                (false, false) => println!("Applied {}", join(&applied)),
+            }
+        }
+        MigrateAction::Down { target, dry_run } => {
+            let reverted = migration::migrate_down(pool, target, dry_run)
+                .await
+                .unwrap_or_else(|e| fail(&e));
+            match (reverted.is_empty(), dry_run) {
+                (true, _) => println!("Nothing to revert"),
+                (false, true) => println!("Would revert {}", join(&reverted)),
+                (false, false) => println!("Reverted {}", join(&reverted)),
+            }
+        }
+        MigrateAction::Status => {
+            let entries = migration::list_migrations(pool)
+                .await
+                .unwrap_or_else(|e| fail(&e));
+            for entry in entries {
+                let installed_on = entry
+                    .installed_on
+                    .map_or(String::new(), |at| at.to_rfc3339());
+                println!(
+                    "{:>4} {:<8} {:<30} {:.16} {}",
+                    entry.version, entry.state, entry.description, entry.checksum, installed_on
+                );
+            }
+        }
+        MigrateAction::Verify => {
+            let status = migration::migration_status(pool)
+                .await
+                .unwrap_or_else(|e| fail(&e));
+            if !status.is_up_to_date() {
+                for (problem, versions) in [
+                    ("pending", &status.pending),
+                    ("modified since applied", &status.modified),
+                    ("unknown to this binary", &status.unknown),
+                    ("failed", &status.failed),
+                ] {
+                    if !versions.is_empty() {
+                        println!("{problem}: {}", join(versions));
+                    }
+                }
+                std::process::exit(1);
+            }
+            let entries = migration::list_migrations(pool)
+                .await
+                .unwrap_or_else(|e| fail(&e));
+            let applied = entries
+                .iter()
+                .filter(|e| e.state == MigrationState::Applied)
+                .count();
+            println!("All {applied} migrations are applied and unmodified");
+        }
+        MigrateAction::Reset { confirmed } => {
+            if !confirmed {
+                fail(&"reset drops every table, pass --yes-i-am-sure to proceed");
+            }
+            migration::reset(pool, url)
+                .await
+                .unwrap_or_else(|e| fail(&e));
+            println!("Dropped every table and applied all migrations");
+        }
+    }
+}
+
 async fn shutdown_signal() {
     // (1)
     let ctrl_c = async {
@@ -52,60 +164,365 @@ async fn shutdown_signal() {
 #[tokio::main]
 async fn main() {
     let _ = dotenv().ok();
-    let _ = setup_tracer();
 
     let args = Arguments::parse();
+    let config = Config::load(&args.config).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(2);
+    });
+    setup_tracer(&config.tracing);
+
     match args.cmd {
-        SubCommand::StartServer { port } => {
-            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription).finish();
+        SubCommand::StartServer => {
             let prometheus_recorder = create_prometheus_recorder();
+            // Changes are published by the database triggers, or by the memory repository,
+            // and fanned out to local subscribers
+            let broker = Broker::default();
+            let pool = match config.server.repository {
+                RepositoryKind::Postgres => {
+                    let pool = db::connect(&config.database).await.unwrap();
+                    if config.database.migrate_on_start {
+                        // Runs in the background so liveness answers, readiness waits for the schema
+                        let pool = pool.clone();
+                        tokio::spawn(async move {
+                            match db::migration::migrate_exclusively(&pool).await {
+                                Ok(applied) if applied.is_empty() => {
+                                    info!("Database schema is up to date")
+                                }
+                                Ok(applied) => info!(?applied, "Applied migrations"),
+                                Err(e) => error!("Migrating the database failed: {e}"),
+                            }
+                        });
+                    }
+                    tokio::spawn(db::listener::forward_notifications(
+                        db::listener_pool(&config.database).unwrap(),
+                        broker.clone(),
+                    ));
+                    spawn_pool_metrics(pool.clone());
+                    Some(pool)
+                }
+                RepositoryKind::Memory => {
+                    warn!("Books and todos are kept in memory and lost when the server stops");
+                    None
+                }
+            };
+
+            let documents = match &config.server.trusted_documents {
+                Some(path) => {
+                    let documents = TrustedDocuments::load(path)
+                        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+                    info!(
+                        "Only executing the {} trusted documents of {}",
+                        documents.len(),
+                        path.display()
+                    );
+                    DocumentMode::Trusted(Arc::new(documents))
+                }
+                None => {
+                    let cache_size = config.persisted_queries.cache_size;
+                    match config.persisted_queries.store {
+                        PersistedQueryStoreKind::Memory => {
+                            DocumentMode::AutomaticPersisted(Arc::new(LruStore::new(cache_size)))
+                        }
+                        PersistedQueryStoreKind::Postgres => {
+                            DocumentMode::AutomaticPersisted(Arc::new(PostgresStore::new(
+                                pool.clone()
+                                    .expect("checked by the configuration validation"),
+                                cache_size,
+                                config.persisted_queries.max_stored,
+                            )))
+                        }
+                    }
+                }
+            };
+
+            let (books, todos): (Arc<dyn BookRepository>, Arc<dyn TodoRepository>) = match &pool {
+                Some(pool) => {
+                    let repository = Arc::new(PostgresRepository::new(pool.clone()));
+                    (repository.clone(), repository)
+                }
+                None => {
+                    let repository = Arc::new(MemoryRepository::new(broker.clone()));
+                    (repository.clone(), repository)
+                }
+            };
+
+            let schema = model::schema_builder(&config.limits, documents)
+                .data(books.clone())
+                .data(todos)
+                .data(broker.clone())
+                .data(DataLoader::new(
+                    BookLoader::new(books.clone()),
+                    tokio::spawn,
+                ))
+                .data(DataLoader::new(AuthorBooksLoader::new(books), tokio::spawn))
+                .finish();
+
+            let jwt = JwtVerifier::new(&config.auth).unwrap_or_else(|e| panic!("{}", e));
+            if jwt.key_count() == 0 {
+                warn!("No JWT keys configured, every bearer token will be rejected");
+            }
 
-            let address = format!("0.0.0.0:{}", port);
+            let address = format!("0.0.0.0:{}", config.server.port);
             info!("Service starting at address: {}", address);
 
+            let limiter = Arc::new(RateLimiter::new(&config.rate_limit));
+
             let app = Router::new()
-                .route("/", get(graphql_playground).post(graphql_handler))
-                .route("/health", get(health))
-                .route("/metrics", get(move || ready(prometheus_recorder.render())))
+                .route(
+                    "/",
+                    get(graphql_get_handler)
+                        .post(graphql_handler)
+                        .layer(middleware::from_fn_with_state(limiter.clone(), rate_limit)),
+                )
+                .route(
+                    "/ws",
+                    get(graphql_ws_handler)
+                        .layer(middleware::from_fn_with_state(limiter.clone(), rate_limit)),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    Arc::new(Authenticator {
+                        jwt,
+                        pool: pool.clone(),
+                        limiter,
+                    }),
+                    authenticate,
+                ))
                 .route_layer(middleware::from_fn(track_metrics))
-                .layer(Extension(schema));
+                // Probes and scrapes are neither authenticated nor counted as API traffic
+                .route("/health/live", get(liveness))
+                .route("/health/ready", get(readiness))
+                .route("/metrics", get(move || ready(prometheus_recorder.render())))
+                .layer(Extension(schema))
+                .layer(Extension(pool));
 
             Server::bind(&address.parse().unwrap())
-                .serve(app.into_make_service())
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                 .with_graceful_shutdown(shutdown_signal())
                 .await
                 .unwrap();
         }
         SubCommand::Sqlx { case } => {
-            let pool = sqlx::postgres::PgPool::connect(db::DB_FOR_DEV)
-                .await
-                .unwrap();
+            let pool = db::connect(&config.database).await.unwrap();
 
             match case {
                 SqlCase::Test => {
                     db::test(&pool).await.unwrap();
                 }
-                SqlCase::Migrate { folder } => match folder {
-                    MigrationFolder::Bookstore => {
-                        let _ = db::migrate_bookstore(&pool).await.unwrap();
-                    }
-                },
+                SqlCase::Migrate { action } => migrate(&pool, &config.database.url, action).await,
                 SqlCase::Bookstore { example } => match example {
-                    BookstoreEx::Create => {
-                        let _ = db::bookstore::create_book_example(&pool).await.unwrap();
-                    }
                     BookstoreEx::Update => {
-                        let _ = db::bookstore::update_book_example(&pool).await.unwrap();
+                        db::bookstore::update_book_example(&pool).await.unwrap();
                     }
                     BookstoreEx::Read { v } => {
                         let _ = db::bookstore::read_book_example(&pool, v).await.unwrap();
                     }
                     BookstoreEx::Transaction => {
-                        let _ = db::bookstore::transaction(&pool).await.unwrap();
+                        db::bookstore::transaction(&pool).await.unwrap();
                     }
                 },
             }
         }
+        SubCommand::Config { action } => match action {
+            ConfigAction::Print => {
+                print!("{}", toml::to_string_pretty(&config.redacted()).unwrap());
+            }
+        },
+        SubCommand::TrustedDocuments { action } => match action {
+            TrustedDocumentsAction::Validate { manifest } => {
+                let documents = TrustedDocuments::load(&manifest)
+                    .unwrap_or_else(|e| panic!("{}: {}", manifest.display(), e));
+                let count = documents.len();
+                let problems = model::validate_manifest(&config.limits, Arc::new(documents)).await;
+
+                for (key, errors) in problems.iter() {
+                    for error in errors {
+                        println!("{key}: {error}");
+                    }
+                }
+                if !problems.is_empty() {
+                    println!("{} of {} documents are invalid", problems.len(), count);
+                    std::process::exit(1);
+                }
+                println!("all {count} documents are valid");
+            }
+        },
+        SubCommand::Schema { action } => match action {
+            SchemaAction::Print { output } => {
+                let sdl = model::sdl();
+                match output {
+                    Some(path) => std::fs::write(&path, sdl)
+                        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e)),
+                    None => print!("{sdl}"),
+                }
+            }
+            SchemaAction::Diff {
+                against,
+                fail_on_dangerous,
+            } => {
+                let old = std::fs::read_to_string(&against)
+                    .unwrap_or_else(|e| panic!("{}: {}", against.display(), e));
+                let changes = model::diff_schema(&old, &model::sdl())
+                    .unwrap_or_else(|e| panic!("{}: {}", against.display(), e));
+
+                for change in &changes {
+                    println!("{:<9} {}: {}", change.severity, change.path, change.message);
+                }
+                let threshold = if fail_on_dangerous {
+                    Severity::Dangerous
+                } else {
+                    Severity::Breaking
+                };
+                let failing = changes.iter().filter(|c| c.severity >= threshold).count();
+                if failing > 0 {
+                    println!("{failing} of {} changes are {threshold}", changes.len());
+                    std::process::exit(1);
+                }
+                println!("{} changes, none {threshold}", changes.len());
+            }
+        },
+        SubCommand::ApiKeys { action } => {
+            let pool = db::connect(&config.database).await.unwrap();
+
+            match action {
+                ApiKeyAction::Mint {
+                    owner,
+                    scopes,
+                    expires_in_days,
+                } => {
+                    let (key, prefix) = generate_api_key();
+                    let expires_at = expires_in_days
+                        .map(|days| chrono::Utc::now() + chrono::Duration::days(days.into()));
+                    let api_key = db::api_key::insert_api_key(
+                        &pool,
+                        &hash_api_key(&key),
+                        &prefix,
+                        &owner,
+                        &scopes,
+                        expires_at,
+                    )
+                    .await
+                    .unwrap();
+
+                    println!("Minted API key {} for {}", api_key.id, api_key.owner);
+                    println!("{key}");
+                    println!("Store it now, it cannot be shown again");
+                }
+                ApiKeyAction::List => {
+                    let keys = db::api_key::fetch_api_keys(&pool).await.unwrap();
+                    for key in keys {
+                        let status = match (key.revoked_at, key.expires_at) {
+                            (Some(revoked_at), _) => format!("revoked {revoked_at}"),
+                            (None, Some(expires_at)) if expires_at <= chrono::Utc::now() => {
+                                format!("expired {expires_at}")
+                            }
+                            (None, Some(expires_at)) => format!("expires {expires_at}"),
+                            (None, None) => "active".to_string(),
+                        };
+                        let last_used = key
+                            .last_used_at
+                            .map_or("never used".to_string(), |at| format!("last used {at}"));
+                        println!(
+                            "{}\t{}...\t{}\t[{}]\tcreated {}\t{}\t{}",
+                            key.id,
+                            key.prefix,
+                            key.owner,
+                            key.scopes.join(" "),
+                            key.created_at,
+                            status,
+                            last_used
+                        );
+                    }
+                }
+                ApiKeyAction::Revoke { id } => {
+                    if db::api_key::revoke_api_key(&pool, id).await.unwrap() {
+                        println!("Revoked API key {id}");
+                    } else {
+                        println!("No active API key with id {id}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        SubCommand::Bookstore { action } => {
+            let pool = db::connect(&config.database).await.unwrap();
+
+            match action {
+                CatalogAction::Import {
+                    input,
+                    format,
+                    rejects,
+                } => {
+                    let file = std::fs::File::open(&input).unwrap_or_else(|e| {
+                        eprintln!("{}: {e}", input.display());
+                        std::process::exit(2)
+                    });
+                    let report = db::catalog::import_books(&pool, file, format)
+                        .await
+                        .unwrap_or_else(|e| {
+                            eprintln!("{}: {e}", input.display());
+                            std::process::exit(1)
+                        });
+
+                    println!(
+                        "Books: {} inserted, {} updated, {} rejected",
+                        report.inserted,
+                        report.updated,
+                        report.rejected.len()
+                    );
+                    if !report.rejected.is_empty() {
+                        match rejects {
+                            Some(path) => {
+                                db::catalog::write_rejections(&path, &report.rejected)
+                                    .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+                                println!("Rejected rows written to {}", path.display());
+                            }
+                            None => {
+                                for rejection in &report.rejected {
+                                    eprintln!("line {}: {}", rejection.line, rejection.reason);
+                                }
+                            }
+                        }
+                        std::process::exit(1);
+                    }
+                }
+                CatalogAction::Export { output, format } => {
+                    let file = std::fs::File::create(&output)
+                        .unwrap_or_else(|e| panic!("{}: {}", output.display(), e));
+                    let count =
+                        db::catalog::export_books(&pool, std::io::BufWriter::new(file), format)
+                            .await
+                            .unwrap();
+                    println!("Exported {count} books to {}", output.display());
+                }
+            }
+        }
+        SubCommand::Seed {
+            files,
+            synthetic,
+            rng_seed,
+        } => {
+            let mut fixture = Fixture::default();
+            for path in &files {
+                let loaded = Fixture::load(path).unwrap_or_else(|e| {
+                    eprintln!("{}: {e}", path.display());
+                    std::process::exit(2)
+                });
+                fixture.extend(loaded);
+            }
+            fixture.books.extend(synthetic_books(synthetic, rng_seed));
+
+            let pool = db::connect(&config.database).await.unwrap();
+            let report = db::seed::seed(&pool, fixture).await.unwrap();
+            println!(
+                "Books: {} inserted, {} updated",
+                report.books.inserted, report.books.updated
+            );
+            println!(
+                "Todos: {} inserted, {} already existed",
+                report.todos_inserted, report.todos_existing
+            );
+        }
         _ => todo!("not implemented"),
     }
 }
@@ -0,0 +1,294 @@
+use crate::db::bookstore::{Book, BookKey, BookOrder, BookPatch, Metadata, SearchHit};
+use crate::db::filter::BookFilter;
+use crate::isbn::Isbn;
+use crate::model::error::ApiError;
+use crate::model::guard::{RoleGuard, ScopeGuard};
+use crate::model::loader::{AuthorBooksLoader, BookLoader};
+use crate::model::repository::BookRepository;
+use crate::model::{list_complexity, UNBOUNDED_LIST_COMPLEXITY};
+use async_graphql::connection::{self, Connection, Edge, OpaqueCursor};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, InputObject, MaybeUndefined, Object, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Page size of `booksConnection` when neither `first` nor `last` is given, and of `searchBooks` without `limit`
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Content of the opaque `booksConnection` cursors.
+/// The order is kept in the cursor so it cannot be reused with a different `orderBy`.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct BookCursor {
+    order: BookOrder,
+    key: BookKey,
+}
+
+type BookConnection = Connection<OpaqueCursor<BookCursor>, Book>;
+
+/// Queries over the bookstore `book` table.
+/// The `BookRepository` is expected to be registered in the schema data.
+/// The catalog changes rarely, so responses made only of these fields may be cached for a minute.
+#[derive(Default)]
+pub(crate) struct BookQuery;
+
+#[Object]
+impl BookQuery {
+    /// All books matching the optional filter ordered by title
+    #[graphql(
+        complexity = "list_complexity(UNBOUNDED_LIST_COMPLEXITY, child_complexity) + filter_complexity(&filter)",
+        cache_control(max_age = 60)
+    )]
+    async fn books(&self, ctx: &Context<'_>, filter: Option<BookFilter>) -> Result<Vec<Book>> {
+        let books = ctx.data::<Arc<dyn BookRepository>>()?;
+        validate_filter(&filter)?;
+        Ok(books.books(filter.as_ref()).await?)
+    }
+
+    /// Look up a single book by its isbn
+    #[graphql(cache_control(max_age = 60))]
+    async fn book(&self, ctx: &Context<'_>, isbn: Isbn) -> Result<Option<Book>> {
+        let loader = ctx.data::<DataLoader<BookLoader>>()?;
+        Ok(loader.load_one(isbn).await?)
+    }
+
+    /// Relay style pagination over the books matching the optional filter, cursors are opaque keyset positions.
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(
+        complexity = "list_complexity(first.or(last).map_or(DEFAULT_PAGE_SIZE, |n| n.max(0) as usize), child_complexity) + filter_complexity(&filter)",
+        cache_control(max_age = 60)
+    )]
+    async fn books_connection(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        order_by: Option<BookOrder>,
+        filter: Option<BookFilter>,
+    ) -> Result<BookConnection> {
+        let books = ctx.data::<Arc<dyn BookRepository>>()?;
+        validate_filter(&filter)?;
+        let order = order_by.unwrap_or_default();
+
+        connection::query(
+            after,
+            before,
+            first,
+            last,
+            |after: Option<OpaqueCursor<BookCursor>>,
+             before: Option<OpaqueCursor<BookCursor>>,
+             first,
+             last| async move {
+                let after = cursor_key(after, order)?;
+                let before = cursor_key(before, order)?;
+                let (limit, from_end) = match (first, last) {
+                    (Some(first), None) => (first, false),
+                    (None, Some(last)) => (last, true),
+                    (None, None) => (DEFAULT_PAGE_SIZE, false),
+                    (Some(_), Some(_)) => unreachable!("connection::query rejects first with last"),
+                };
+                if limit > MAX_PAGE_SIZE {
+                    return Err(ApiError::InvalidInput(format!(
+                        "at most {MAX_PAGE_SIZE} books can be requested at once"
+                    ))
+                    .into());
+                }
+
+                let page = books
+                    .books_page(
+                        order,
+                        filter.as_ref(),
+                        after.as_ref(),
+                        before.as_ref(),
+                        limit,
+                        from_end,
+                    )
+                    .await?;
+
+                let (has_previous_page, has_next_page) = if from_end {
+                    (page.has_more, before.is_some())
+                } else {
+                    (after.is_some(), page.has_more)
+                };
+                let mut connection = Connection::new(has_previous_page, has_next_page);
+                connection.edges.extend(page.books.into_iter().map(|book| {
+                    let cursor = BookCursor {
+                        order,
+                        key: BookKey::from(&book),
+                    };
+                    Edge::new(OpaqueCursor(cursor), book)
+                }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+
+    /// Full text search over title, author and tags, best matches first.
+    /// Every word of `query` must match the start of a word in the book.
+    #[graphql(
+        complexity = "list_complexity(limit.map_or(DEFAULT_PAGE_SIZE, |n| n.max(0) as usize), child_complexity)",
+        cache_control(max_age = 60)
+    )]
+    async fn search_books(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        limit: Option<i32>,
+    ) -> Result<Vec<SearchHit>> {
+        let books = ctx.data::<Arc<dyn BookRepository>>()?;
+        let limit = match limit {
+            None => DEFAULT_PAGE_SIZE,
+            Some(limit) if limit > 0 && limit as usize <= MAX_PAGE_SIZE => limit as usize,
+            Some(_) => {
+                return Err(ApiError::InvalidInput(format!(
+                    "limit must be between 1 and {MAX_PAGE_SIZE}"
+                ))
+                .into())
+            }
+        };
+
+        Ok(books.search_books(&query, limit).await?)
+    }
+
+    /// All books written by the given author ordered by title
+    #[graphql(
+        complexity = "list_complexity(UNBOUNDED_LIST_COMPLEXITY, child_complexity)",
+        cache_control(max_age = 60)
+    )]
+    async fn books_by_author(&self, ctx: &Context<'_>, author: String) -> Result<Vec<Book>> {
+        let loader = ctx.data::<DataLoader<AuthorBooksLoader>>()?;
+        Ok(loader.load_one(author).await?.unwrap_or_default())
+    }
+}
+
+/// Fields of `Book` which need to load related data, batched through the data loaders.
+#[ComplexObject]
+impl Book {
+    /// Other books written by the same author ordered by title
+    #[graphql(complexity = "list_complexity(UNBOUNDED_LIST_COMPLEXITY, child_complexity)")]
+    async fn more_by_author(&self, ctx: &Context<'_>) -> Result<Vec<Book>> {
+        let loader = ctx.data::<DataLoader<AuthorBooksLoader>>()?;
+        let books = loader
+            .load_one(self.author.clone())
+            .await?
+            .unwrap_or_default();
+
+        Ok(books
+            .into_iter()
+            .filter(|book| book.isbn != self.isbn)
+            .collect())
+    }
+}
+
+/// Every condition of a filter is charged like a field, so large filters count against the complexity limit
+fn filter_complexity(filter: &Option<BookFilter>) -> usize {
+    filter.as_ref().map_or(0, BookFilter::terms)
+}
+
+fn validate_filter(filter: &Option<BookFilter>) -> Result<(), ApiError> {
+    filter
+        .as_ref()
+        .map_or(Ok(()), BookFilter::validate)
+        .map_err(ApiError::InvalidInput)
+}
+
+fn cursor_key(
+    cursor: Option<OpaqueCursor<BookCursor>>,
+    order: BookOrder,
+) -> Result<Option<BookKey>, ApiError> {
+    match cursor {
+        Some(OpaqueCursor(cursor)) if cursor.order != order => Err(ApiError::InvalidInput(
+            "cursor was created for a different orderBy".to_string(),
+        )),
+        Some(OpaqueCursor(cursor)) => Ok(Some(cursor.key)),
+        None => Ok(None),
+    }
+}
+
+#[derive(InputObject)]
+pub(crate) struct CreateBookInput {
+    title: String,
+    author: String,
+    isbn: Isbn,
+    metadata: Option<Metadata>,
+}
+
+/// Fields left out are not changed, an explicit `metadata: null` clears the metadata.
+#[derive(InputObject)]
+pub(crate) struct UpdateBookInput {
+    title: Option<String>,
+    author: Option<String>,
+    metadata: MaybeUndefined<Metadata>,
+}
+
+impl From<UpdateBookInput> for BookPatch {
+    fn from(input: UpdateBookInput) -> Self {
+        let metadata = match input.metadata {
+            MaybeUndefined::Undefined => None,
+            MaybeUndefined::Null => Some(None),
+            MaybeUndefined::Value(metadata) => Some(Some(metadata)),
+        };
+
+        BookPatch {
+            title: input.title,
+            author: input.author,
+            metadata,
+        }
+    }
+}
+
+/// Mutations over the bookstore `book` table, books are identified by isbn.
+/// Writing requires the `books:write` scope, deleting the `admin` role.
+#[derive(Default)]
+pub(crate) struct BookMutation;
+
+#[Object]
+impl BookMutation {
+    /// Create a new book, fails with `DUPLICATE_ISBN` if the isbn is taken
+    #[graphql(guard = "ScopeGuard::new(\"books:write\")")]
+    async fn create_book(&self, ctx: &Context<'_>, input: CreateBookInput) -> Result<Book> {
+        let books = ctx.data::<Arc<dyn BookRepository>>()?;
+        if let Some(metadata) = &input.metadata {
+            metadata.validate().map_err(ApiError::InvalidInput)?;
+        }
+        let book = Book {
+            title: input.title,
+            author: input.author,
+            isbn: input.isbn,
+            metadata: input.metadata,
+        };
+
+        Ok(books.create_book(book).await?)
+    }
+
+    /// Partially update the book with the given isbn, fails with `NOT_FOUND` if there is no such book
+    #[graphql(guard = "ScopeGuard::new(\"books:write\")")]
+    async fn update_book(
+        &self,
+        ctx: &Context<'_>,
+        isbn: Isbn,
+        input: UpdateBookInput,
+    ) -> Result<Book> {
+        let books = ctx.data::<Arc<dyn BookRepository>>()?;
+        if let MaybeUndefined::Value(metadata) = &input.metadata {
+            metadata.validate().map_err(ApiError::InvalidInput)?;
+        }
+        let patch = BookPatch::from(input);
+
+        books
+            .update_book(&isbn, &patch)
+            .await?
+            .ok_or_else(|| ApiError::BookNotFound(isbn).into())
+    }
+
+    /// Delete the book with the given isbn, returns false if there was no such book
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn delete_book(&self, ctx: &Context<'_>, isbn: Isbn) -> Result<bool> {
+        let books = ctx.data::<Arc<dyn BookRepository>>()?;
+        Ok(books.delete_book(&isbn).await?)
+    }
+}
@@ -0,0 +1,39 @@
+use crate::model::error::server_error;
+use crate::rate_limit::{rate_limited_message, RateLimitTicket};
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextValidation};
+use async_graphql::{ServerError, ValidationResult};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Charges the complexity of a validated query to the client's rate limit.
+/// Requests without a `RateLimitTicket` (manifest validation, tests) are not limited, subscriptions get
+/// the ticket of their WebSocket connection.
+pub(crate) struct QueryCostExtension;
+
+impl ExtensionFactory for QueryCostExtension {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryCostExtension)
+    }
+}
+
+#[async_trait]
+impl Extension for QueryCostExtension {
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+
+        if let Some(ticket) = ctx.data_opt::<RateLimitTicket>() {
+            ticket.charge(result.complexity).map_err(|retry_after| {
+                vec![server_error(
+                    "RATE_LIMITED",
+                    rate_limited_message(retry_after),
+                )]
+            })?;
+        }
+
+        Ok(result)
+    }
+}
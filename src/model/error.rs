@@ -0,0 +1,91 @@
+use crate::isbn::Isbn;
+use async_graphql::{Error, ErrorExtensionValues, ErrorExtensions, ServerError};
+use std::sync::Arc;
+use tracing::error;
+
+/// Name of the unique index guarding `book.isbn`, see `0001_create_books_table.sql`
+const BOOK_ISBN_IDX: &str = "book_isbn_idx";
+
+/// Errors returned to GraphQL clients.
+/// Each variant carries a stable `code` extension clients can match on instead of the message.
+#[derive(Debug, Clone)]
+pub(crate) enum ApiError {
+    DuplicateIsbn(Isbn),
+    BookNotFound(Isbn),
+    TodoNotFound(i64),
+    InvalidInput(String),
+    Unauthenticated,
+    Forbidden(String),
+    Database(Arc<sqlx::Error>),
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::DuplicateIsbn(_) => "DUPLICATE_ISBN",
+            ApiError::BookNotFound(_) | ApiError::TodoNotFound(_) => "NOT_FOUND",
+            ApiError::InvalidInput(_) => "BAD_USER_INPUT",
+            ApiError::Unauthenticated => "UNAUTHENTICATED",
+            ApiError::Forbidden(_) => "FORBIDDEN",
+            ApiError::Database(_) => "INTERNAL_SERVER_ERROR",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::DuplicateIsbn(isbn) => format!("a book with isbn {isbn} already exists"),
+            ApiError::BookNotFound(isbn) => format!("no book with isbn {isbn}"),
+            ApiError::TodoNotFound(id) => format!("no todo with id {id}"),
+            ApiError::InvalidInput(reason) => reason.clone(),
+            ApiError::Unauthenticated => "authentication required".to_string(),
+            ApiError::Forbidden(reason) => reason.clone(),
+            // Do not leak database details to clients, they are logged instead
+            ApiError::Database(_) => "internal database error".to_string(),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::Database(Arc::new(e))
+    }
+}
+
+impl ErrorExtensions for ApiError {
+    fn extend(&self) -> Error {
+        if let ApiError::Database(e) = self {
+            error!("database error: {}", e);
+        }
+
+        let code = self.code();
+        Error::new(self.message()).extend_with(|_, e| e.set("code", code))
+    }
+}
+
+impl From<ApiError> for Error {
+    fn from(e: ApiError) -> Self {
+        e.extend()
+    }
+}
+
+/// Map a failed book insert, turning a violation of `book_isbn_idx` into `ApiError::DuplicateIsbn`
+pub(crate) fn book_insert_error(e: sqlx::Error, isbn: &Isbn) -> ApiError {
+    match &e {
+        sqlx::Error::Database(db_err)
+            if db_err.is_unique_violation() && db_err.constraint() == Some(BOOK_ISBN_IDX) =>
+        {
+            ApiError::DuplicateIsbn(isbn.clone())
+        }
+        _ => ApiError::from(e),
+    }
+}
+
+/// Request level error (rejected before any resolver ran) carrying a `code` extension
+pub(crate) fn server_error(code: &str, message: String) -> ServerError {
+    let mut extensions = ErrorExtensionValues::default();
+    extensions.set("code", code);
+
+    let mut error = ServerError::new(message, None);
+    error.extensions = Some(extensions);
+    error
+}
@@ -0,0 +1,52 @@
+use crate::auth::Identity;
+use crate::model::error::ApiError;
+use async_graphql::{Context, Guard, Result};
+use async_trait::async_trait;
+
+/// Only lets callers through which carry the given role claim
+pub(crate) struct RoleGuard {
+    role: &'static str,
+}
+
+impl RoleGuard {
+    pub(crate) fn new(role: &'static str) -> Self {
+        RoleGuard { role }
+    }
+}
+
+#[async_trait]
+impl Guard for RoleGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        let identity = ctx
+            .data_opt::<Identity>()
+            .ok_or(ApiError::Unauthenticated)?;
+        if !identity.roles.contains(self.role) {
+            return Err(ApiError::Forbidden(format!("requires the {} role", self.role)).into());
+        }
+        Ok(())
+    }
+}
+
+/// Only lets callers through which were granted the given scope
+pub(crate) struct ScopeGuard {
+    scope: &'static str,
+}
+
+impl ScopeGuard {
+    pub(crate) fn new(scope: &'static str) -> Self {
+        ScopeGuard { scope }
+    }
+}
+
+#[async_trait]
+impl Guard for ScopeGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        let identity = ctx
+            .data_opt::<Identity>()
+            .ok_or(ApiError::Unauthenticated)?;
+        if !identity.scopes.contains(self.scope) {
+            return Err(ApiError::Forbidden(format!("requires the {} scope", self.scope)).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use crate::config::QueryLimits;
+use crate::model::error::server_error;
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextValidation,
+};
+use async_graphql::parser::types::{ExecutableDocument, Selection, SelectionSet};
+use async_graphql::{Positioned, ServerError, ValidationResult, Variables};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Rejects queries exceeding the configured `QueryLimits` with a `code` extension clients can match on.
+/// The only check of depth and complexity, the schema sets no limits of its own besides the parser recursion depth.
+pub(crate) struct QueryLimitsExtension {
+    limits: QueryLimits,
+}
+
+impl QueryLimitsExtension {
+    pub fn new(limits: QueryLimits) -> Self {
+        QueryLimitsExtension { limits }
+    }
+}
+
+impl ExtensionFactory for QueryLimitsExtension {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryLimitsExtension {
+            limits: self.limits.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Extension for QueryLimitsExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> Result<ExecutableDocument, ServerError> {
+        if query.len() > self.limits.max_query_length {
+            return Err(server_error(
+                "QUERY_TOO_LONG",
+                format!(
+                    "Query is longer than {} bytes.",
+                    self.limits.max_query_length
+                ),
+            ));
+        }
+
+        // The schema checks the recursion depth while parsing
+        let document = next.run(ctx, query, variables).await.map_err(|e| {
+            match e.message.starts_with("The recursion depth") {
+                true => server_error("QUERY_TOO_DEEP", e.message),
+                false => e,
+            }
+        })?;
+
+        let aliases = count_aliases(&document);
+        if aliases > self.limits.max_aliases {
+            return Err(server_error(
+                "TOO_MANY_ALIASES",
+                format!(
+                    "Query uses {} aliases, at most {} are allowed.",
+                    aliases, self.limits.max_aliases
+                ),
+            ));
+        }
+
+        Ok(document)
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+
+        if result.depth > self.limits.max_depth {
+            return Err(vec![server_error(
+                "QUERY_TOO_DEEP",
+                format!(
+                    "Query is nested {} levels deep, at most {} are allowed.",
+                    result.depth, self.limits.max_depth
+                ),
+            )]);
+        }
+        if result.complexity > self.limits.max_complexity {
+            return Err(vec![server_error(
+                "QUERY_TOO_COMPLEX",
+                format!(
+                    "Query has a complexity of {}, at most {} is allowed.",
+                    result.complexity, self.limits.max_complexity
+                ),
+            )]);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Aliases used anywhere in the document, fragments included
+fn count_aliases(document: &ExecutableDocument) -> usize {
+    let in_operations: usize = document
+        .operations
+        .iter()
+        .map(|(_, operation)| count_in_selection_set(&operation.node.selection_set))
+        .sum();
+    let in_fragments: usize = document
+        .fragments
+        .values()
+        .map(|fragment| count_in_selection_set(&fragment.node.selection_set))
+        .sum();
+
+    in_operations + in_fragments
+}
+
+fn count_in_selection_set(selection_set: &Positioned<SelectionSet>) -> usize {
+    selection_set
+        .node
+        .items
+        .iter()
+        .map(|selection| match &selection.node {
+            Selection::Field(field) => {
+                usize::from(field.node.alias.is_some())
+                    + count_in_selection_set(&field.node.selection_set)
+            }
+            Selection::InlineFragment(fragment) => {
+                count_in_selection_set(&fragment.node.selection_set)
+            }
+            Selection::FragmentSpread(_) => 0,
+        })
+        .sum()
+}
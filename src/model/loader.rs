@@ -0,0 +1,64 @@
+use crate::db::bookstore::Book;
+use crate::isbn::Isbn;
+use crate::model::error::ApiError;
+use crate::model::repository::BookRepository;
+use async_graphql::dataloader::Loader;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Batches book lookups by isbn into one `BookRepository::books_by_isbns` call,
+/// a single `WHERE isbn = ANY($1)` statement with Postgres.
+/// Register it wrapped in a `DataLoader` in the schema data.
+pub(crate) struct BookLoader {
+    books: Arc<dyn BookRepository>,
+}
+
+impl BookLoader {
+    pub fn new(books: Arc<dyn BookRepository>) -> Self {
+        BookLoader { books }
+    }
+}
+
+#[async_trait]
+impl Loader<Isbn> for BookLoader {
+    type Value = Book;
+    type Error = ApiError;
+
+    async fn load(&self, isbns: &[Isbn]) -> Result<HashMap<Isbn, Book>, Self::Error> {
+        let books = self.books.books_by_isbns(isbns).await?;
+
+        Ok(books
+            .into_iter()
+            .map(|book| (book.isbn.clone(), book))
+            .collect())
+    }
+}
+
+/// Batches lookups of all books by an author into one `BookRepository::books_by_authors` call.
+pub(crate) struct AuthorBooksLoader {
+    books: Arc<dyn BookRepository>,
+}
+
+impl AuthorBooksLoader {
+    pub fn new(books: Arc<dyn BookRepository>) -> Self {
+        AuthorBooksLoader { books }
+    }
+}
+
+#[async_trait]
+impl Loader<String> for AuthorBooksLoader {
+    type Value = Vec<Book>;
+    type Error = ApiError;
+
+    async fn load(&self, authors: &[String]) -> Result<HashMap<String, Vec<Book>>, Self::Error> {
+        let books = self.books.books_by_authors(authors).await?;
+
+        let mut by_author: HashMap<String, Vec<Book>> = HashMap::new();
+        for book in books {
+            by_author.entry(book.author.clone()).or_default().push(book);
+        }
+
+        Ok(by_author)
+    }
+}
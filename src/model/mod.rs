@@ -1,16 +1,421 @@
-use async_graphql::{Context, Object, Schema};
-use async_graphql::{EmptyMutation, EmptySubscription};
+use crate::config::QueryLimits;
+use async_graphql::{Context, MergedObject, Object, Schema, SchemaBuilder};
+use std::sync::Arc;
 
-pub(crate) type ServiceSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+mod book;
+mod cost;
+mod error;
+mod guard;
+mod limits;
+mod loader;
+mod persisted_queries;
+mod repository;
+mod schema_diff;
+mod subscription;
+mod todo;
+mod trusted_documents;
+
+pub(crate) use book::{BookMutation, BookQuery};
+pub(crate) use error::server_error;
+pub(crate) use loader::{AuthorBooksLoader, BookLoader};
+pub(crate) use persisted_queries::{HttpGet, LruStore, PersistedQueryStore, PostgresStore};
+pub(crate) use repository::{BookRepository, MemoryRepository, PostgresRepository, TodoRepository};
+pub(crate) use schema_diff::{diff_schema, Severity};
+pub(crate) use subscription::SubscriptionRoot;
+pub(crate) use todo::{TodoMutation, TodoQuery};
+pub(crate) use trusted_documents::{validate_manifest, TrustedDocuments};
+
+pub(crate) type ServiceSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Complexity multiplier of list fields which have no page size argument
+pub(crate) const UNBOUNDED_LIST_COMPLEXITY: usize = 50;
+
+/// Complexity of a list of `items` elements costing `child_complexity` each.
+/// Capped far below `usize::MAX` because async-graphql adds up sibling fields without saturating.
+pub(crate) fn list_complexity(items: usize, child_complexity: usize) -> usize {
+    items
+        .saturating_mul(child_complexity)
+        .min(u32::MAX as usize)
+}
+
+/// Which operation documents the service accepts
+pub(crate) enum DocumentMode {
+    /// Any valid document, clients may register documents as automatic persisted queries
+    AutomaticPersisted(Arc<dyn PersistedQueryStore>),
+    /// Only the documents of the trusted documents manifest
+    Trusted(Arc<TrustedDocuments>),
+}
+
+/// Start building the service schema with the query limits and document mode applied.
+/// The caller registers the schema data (repositories, broker, loaders) before finishing it.
+pub(crate) fn schema_builder(
+    limits: &QueryLimits,
+    documents: DocumentMode,
+) -> SchemaBuilder<QueryRoot, MutationRoot, SubscriptionRoot> {
+    let builder = Schema::build(
+        QueryRoot::default(),
+        MutationRoot::default(),
+        SubscriptionRoot,
+    )
+    // Depth and complexity are checked by `QueryLimitsExtension`, the recursion depth protects the parser
+    .limit_recursive_depth(limits.max_depth);
+
+    // Registered before the limits so persisted queries are only stored once they passed the limits
+    let builder = match documents {
+        DocumentMode::AutomaticPersisted(store) => {
+            builder.extension(persisted_queries::PersistedQueries::new(store))
+        }
+        DocumentMode::Trusted(documents) => {
+            builder.extension(trusted_documents::TrustedDocumentsOnly::new(documents))
+        }
+    };
+
+    // Registered before the limits so only queries within the limits are charged
+    builder
+        .extension(cost::QueryCostExtension)
+        .extension(limits::QueryLimitsExtension::new(limits.clone()))
+}
+
+/// The SDL of the service schema, which does not depend on the limits, document mode or schema data
+pub(crate) fn sdl() -> String {
+    Schema::build(
+        QueryRoot::default(),
+        MutationRoot::default(),
+        SubscriptionRoot,
+    )
+    .finish()
+    .sdl()
+}
 
 /// This is the Query object within your schema. It is the root of all queries users can use at your service.
-pub(crate) struct QueryRoot;
+/// Each domain contributes its own query object which are merged together here.
+#[derive(MergedObject, Default)]
+pub(crate) struct QueryRoot(HelloQuery, BookQuery, TodoQuery);
+
+#[derive(Default)]
+pub(crate) struct HelloQuery;
 
-/// The implementation of QueryRoot contains all queries your service supports.
+/// The implementation of HelloQuery contains the static queries your service supports.
 #[Object]
-impl QueryRoot {
+impl HelloQuery {
     /// hello is your first query. It just returns a static string for now
     async fn hello(&self, _ctx: &Context<'_>) -> &'static str {
         "Hello World"
     }
 }
+
+/// This is the Mutation object within your schema. It is the root of all mutations users can use at your service.
+#[derive(MergedObject, Default)]
+pub(crate) struct MutationRoot(BookMutation, TodoMutation);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+    use crate::events::Broker;
+    use async_graphql::dataloader::DataLoader;
+    use serde_json::{json, Value};
+    use std::num::NonZeroUsize;
+
+    /// The service schema over a `MemoryRepository`, wired like `start-server --repository memory`
+    fn memory_schema() -> ServiceSchema {
+        let broker = Broker::default();
+        let repository = Arc::new(MemoryRepository::new(broker.clone()));
+        let books: Arc<dyn BookRepository> = repository.clone();
+        let todos: Arc<dyn TodoRepository> = repository;
+        let store = Arc::new(LruStore::new(NonZeroUsize::new(10).unwrap()));
+
+        schema_builder(
+            &QueryLimits::default(),
+            DocumentMode::AutomaticPersisted(store),
+        )
+        .data(books.clone())
+        .data(todos)
+        .data(broker)
+        .data(DataLoader::new(
+            BookLoader::new(books.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(AuthorBooksLoader::new(books), tokio::spawn))
+        .finish()
+    }
+
+    fn identity(scopes: &[&str], roles: &[&str]) -> Identity {
+        Identity {
+            subject: "tester".to_string(),
+            api_key: None,
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+        }
+    }
+
+    fn writer() -> Option<Identity> {
+        Some(identity(&["books:write"], &[]))
+    }
+
+    /// Execute `query` and return its data, failing on any error
+    async fn data(schema: &ServiceSchema, query: &str, identity: Option<Identity>) -> Value {
+        let response = execute(schema, query, identity).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    }
+
+    /// Execute `query` and return the code of its first error
+    async fn error_code(schema: &ServiceSchema, query: &str, identity: Option<Identity>) -> String {
+        let response = execute(schema, query, identity).await;
+        let error = response.errors.first().expect("the query should fail");
+        match error.extensions.as_ref().and_then(|e| e.get("code")) {
+            Some(async_graphql::Value::String(code)) => code.clone(),
+            other => panic!("no error code in {error:?}: {other:?}"),
+        }
+    }
+
+    async fn execute(
+        schema: &ServiceSchema,
+        query: &str,
+        identity: Option<Identity>,
+    ) -> async_graphql::Response {
+        let mut request = async_graphql::Request::new(query);
+        if let Some(identity) = identity {
+            request = request.data(identity);
+        }
+        schema.execute(request).await
+    }
+
+    const CREATE_DUNE: &str = r#"mutation {
+        createBook(input: {
+            title: "Dune", author: "Frank Herbert", isbn: "0-441-17271-7",
+            metadata: { avgReview: 4.5, tags: ["scifi", "classic"] }
+        }) { isbn title }
+    }"#;
+
+    #[tokio::test]
+    async fn books_round_trip() {
+        let schema = memory_schema();
+
+        assert_eq!(
+            data(&schema, CREATE_DUNE, writer()).await,
+            json!({ "createBook": { "isbn": "9780441172719", "title": "Dune" } })
+        );
+        data(
+            &schema,
+            r#"mutation { createBook(input: {
+                title: "Children of Dune", author: "Frank Herbert", isbn: "9780441104024"
+            }) { isbn } }"#,
+            writer(),
+        )
+        .await;
+
+        assert_eq!(
+            data(
+                &schema,
+                r#"{ book(isbn: "0441172717") { title metadata { tags } moreByAuthor { title } } }"#,
+                None,
+            )
+            .await,
+            json!({ "book": {
+                "title": "Dune",
+                "metadata": { "tags": ["scifi", "classic"] },
+                "moreByAuthor": [{ "title": "Children of Dune" }],
+            } })
+        );
+        assert_eq!(
+            data(
+                &schema,
+                r#"{ booksConnection(first: 1, orderBy: TITLE) {
+                    edges { node { title } } pageInfo { hasNextPage }
+                } }"#,
+                None,
+            )
+            .await,
+            json!({ "booksConnection": {
+                "edges": [{ "node": { "title": "Children of Dune" } }],
+                "pageInfo": { "hasNextPage": true },
+            } })
+        );
+        assert_eq!(
+            data(
+                &schema,
+                r#"{ searchBooks(query: "dun") { book { isbn } } }"#,
+                None,
+            )
+            .await["searchBooks"]
+                .as_array()
+                .map(Vec::len),
+            Some(2)
+        );
+
+        assert_eq!(
+            data(
+                &schema,
+                r#"mutation { updateBook(isbn: "9780441172719", input: { title: "Dune (1965)", metadata: null }) {
+                    title metadata { tags }
+                } }"#,
+                writer(),
+            )
+            .await,
+            json!({ "updateBook": { "title": "Dune (1965)", "metadata": null } })
+        );
+        assert_eq!(
+            data(
+                &schema,
+                r#"mutation { deleteBook(isbn: "9780441172719") }"#,
+                Some(identity(&[], &["admin"])),
+            )
+            .await,
+            json!({ "deleteBook": true })
+        );
+        assert_eq!(
+            data(
+                &schema,
+                r#"{ book(isbn: "9780441172719") { title } }"#,
+                None
+            )
+            .await,
+            json!({ "book": null })
+        );
+    }
+
+    #[tokio::test]
+    async fn book_mutations_are_checked() {
+        let schema = memory_schema();
+
+        assert_eq!(
+            error_code(&schema, CREATE_DUNE, None).await,
+            "UNAUTHENTICATED"
+        );
+        assert_eq!(
+            error_code(&schema, CREATE_DUNE, Some(identity(&[], &[]))).await,
+            "FORBIDDEN"
+        );
+        data(&schema, CREATE_DUNE, writer()).await;
+        assert_eq!(
+            error_code(&schema, CREATE_DUNE, writer()).await,
+            "DUPLICATE_ISBN"
+        );
+        assert_eq!(
+            error_code(
+                &schema,
+                r#"mutation { updateBook(isbn: "9780306406157", input: { title: "Nope" }) { title } }"#,
+                writer(),
+            )
+            .await,
+            "NOT_FOUND"
+        );
+        assert_eq!(
+            error_code(
+                &schema,
+                r#"mutation { updateBook(isbn: "9780441172719", input: {
+                    metadata: { avgReview: 4, tags: ["scifi|classic"] }
+                }) { title } }"#,
+                writer(),
+            )
+            .await,
+            "BAD_USER_INPUT"
+        );
+        assert_eq!(
+            error_code(
+                &schema,
+                r#"mutation { deleteBook(isbn: "9780441172719") }"#,
+                writer(),
+            )
+            .await,
+            "FORBIDDEN"
+        );
+    }
+
+    #[tokio::test]
+    async fn books_connection_rejects_first_with_last() {
+        let schema = memory_schema();
+        let response = execute(
+            &schema,
+            "{ booksConnection(first: 1, last: 1) { edges { cursor } } }",
+            None,
+        )
+        .await;
+
+        assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+        assert!(response.errors[0]
+            .message
+            .contains("cannot exist at the same time"));
+    }
+
+    #[tokio::test]
+    async fn large_filters_are_rejected() {
+        let schema = memory_schema();
+        let wide = vec![r#"{ titleContains: "a" }"#; 101].join(", ");
+        let deep = (0..6).fold("{}".to_string(), |nested, _| {
+            format!("{{ and: [{nested}] }}")
+        });
+
+        for filter in [format!("{{ or: [{wide}] }}"), deep] {
+            assert_eq!(
+                error_code(
+                    &schema,
+                    &format!("{{ books(filter: {filter}) {{ isbn }} }}"),
+                    None
+                )
+                .await,
+                "BAD_USER_INPUT",
+                "{filter}"
+            );
+        }
+        data(
+            &schema,
+            r#"{ booksConnection(filter: { or: [{ titleContains: "a" }, { authorContains: "b" }] }) {
+                edges { cursor }
+            } }"#,
+            None,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn todos_round_trip() {
+        let schema = memory_schema();
+
+        assert_eq!(
+            data(
+                &schema,
+                r#"mutation {
+                    first: addTodo(description: "write tests") { id done }
+                    second: addTodo(description: "ship it") { id }
+                }"#,
+                None,
+            )
+            .await,
+            json!({ "first": { "id": 1, "done": false }, "second": { "id": 2 } })
+        );
+        assert_eq!(
+            data(
+                &schema,
+                r#"mutation {
+                    toggleTodo(id: 1) { done }
+                    renameTodo(id: 2, description: "ship it today") { description }
+                }"#,
+                None,
+            )
+            .await,
+            json!({
+                "toggleTodo": { "done": true },
+                "renameTodo": { "description": "ship it today" },
+            })
+        );
+        assert_eq!(
+            data(&schema, "{ todos(done: false) { id description } }", None).await,
+            json!({ "todos": [{ "id": 2, "description": "ship it today" }] })
+        );
+        assert_eq!(
+            data(&schema, "mutation { deleteTodo(id: 1) }", None,).await,
+            json!({ "deleteTodo": true })
+        );
+        assert_eq!(
+            data(&schema, "{ todos { id } }", None).await,
+            json!({ "todos": [{ "id": 2 }] })
+        );
+        assert_eq!(
+            error_code(&schema, "mutation { toggleTodo(id: 1) { done } }", None).await,
+            "NOT_FOUND"
+        );
+    }
+}
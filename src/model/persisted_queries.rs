@@ -0,0 +1,271 @@
+use crate::db::{self, persisted_query};
+use crate::model::error::server_error;
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextPrepareRequest,
+    NextValidation,
+};
+use async_graphql::parser::types::{ExecutableDocument, OperationType};
+use async_graphql::{from_value, Request, ServerError, ValidationResult, Variables};
+use async_trait::async_trait;
+use lru::LruCache;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+/// Marks requests received over HTTP GET, only queries may be executed for them
+pub(crate) struct HttpGet;
+
+/// Where automatic persisted queries are kept, keyed by the sha256 hash of the query text
+#[async_trait]
+pub(crate) trait PersistedQueryStore: Send + Sync + 'static {
+    async fn get(&self, sha256_hash: &str) -> Option<String>;
+
+    async fn put(&self, sha256_hash: &str, query: &str);
+}
+
+/// In-process LRU cache, each instance has to learn every query once
+pub(crate) struct LruStore {
+    cache: Mutex<LruCache<String, String>>,
+}
+
+impl LruStore {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        LruStore {
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+#[async_trait]
+impl PersistedQueryStore for LruStore {
+    async fn get(&self, sha256_hash: &str) -> Option<String> {
+        self.cache.lock().unwrap().get(sha256_hash).cloned()
+    }
+
+    async fn put(&self, sha256_hash: &str, query: &str) {
+        self.cache
+            .lock()
+            .unwrap()
+            .put(sha256_hash.to_string(), query.to_string());
+    }
+}
+
+/// Queries shared by all instances through the `persisted_queries` table, with an LRU cache in front.
+/// The table keeps the `max_stored` most recently registered queries.
+/// Database errors are logged and treated as a cache miss, the client then simply sends the full query.
+pub(crate) struct PostgresStore {
+    pool: PgPool,
+    cache: LruStore,
+    max_stored: NonZeroUsize,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool, capacity: NonZeroUsize, max_stored: NonZeroUsize) -> Self {
+        PostgresStore {
+            pool,
+            cache: LruStore::new(capacity),
+            max_stored,
+        }
+    }
+}
+
+#[async_trait]
+impl PersistedQueryStore for PostgresStore {
+    async fn get(&self, sha256_hash: &str) -> Option<String> {
+        if let Some(query) = self.cache.get(sha256_hash).await {
+            return Some(query);
+        }
+
+        let fetched = async {
+            let mut conn = db::acquire(&self.pool).await?;
+            persisted_query::fetch_persisted_query(&mut *conn, sha256_hash).await
+        };
+        match fetched.await {
+            Ok(Some(query)) => {
+                self.cache.put(sha256_hash, &query).await;
+                Some(query)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                error!("failed to load persisted query {}: {}", sha256_hash, e);
+                None
+            }
+        }
+    }
+
+    async fn put(&self, sha256_hash: &str, query: &str) {
+        self.cache.put(sha256_hash, query).await;
+        let stored = async {
+            let mut conn = db::acquire(&self.pool).await?;
+            let max_stored = self.max_stored.get() as i64;
+            persisted_query::insert_persisted_query(&mut conn, sha256_hash, query, max_stored).await
+        };
+        if let Err(e) = stored.await {
+            error!("failed to store persisted query {}: {}", sha256_hash, e);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PersistedQuery {
+    pub version: i32,
+    #[serde(rename = "sha256Hash")]
+    pub sha256_hash: String,
+}
+
+/// Apollo automatic persisted queries, see https://www.apollographql.com/docs/apollo-server/performance/apq
+///
+/// Unlike the extension shipped with async-graphql the store keeps the query text, so it can live in Postgres,
+/// and a query is only stored once it passed validation, which includes the `QueryLimits` checks.
+/// Register it before `QueryLimitsExtension`. It also rejects mutations sent over GET.
+pub(crate) struct PersistedQueries {
+    store: Arc<dyn PersistedQueryStore>,
+}
+
+impl PersistedQueries {
+    pub fn new(store: Arc<dyn PersistedQueryStore>) -> Self {
+        PersistedQueries { store }
+    }
+}
+
+impl ExtensionFactory for PersistedQueries {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(PersistedQueriesExtension {
+            store: self.store.clone(),
+            register: Mutex::new(None),
+        })
+    }
+}
+
+/// Created for every request
+struct PersistedQueriesExtension {
+    store: Arc<dyn PersistedQueryStore>,
+    /// Hash to register the query under, with the query once it parsed
+    register: Mutex<Option<(String, Option<String>)>>,
+}
+
+#[async_trait]
+impl Extension for PersistedQueriesExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        mut request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> Result<Request, ServerError> {
+        let Some(persisted_query) = take_persisted_query(&mut request)? else {
+            return next.run(ctx, request).await;
+        };
+
+        if request.query.is_empty() {
+            // Apollo clients match on this exact message to resend the full query
+            request.query = self
+                .store
+                .get(&persisted_query.sha256_hash)
+                .await
+                .ok_or_else(|| {
+                    server_error(
+                        "PERSISTED_QUERY_NOT_FOUND",
+                        "PersistedQueryNotFound".to_string(),
+                    )
+                })?;
+        } else if sha256_hex(&request.query) == persisted_query.sha256_hash {
+            *self.register.lock().unwrap() = Some((persisted_query.sha256_hash, None));
+        } else {
+            return Err(server_error(
+                "PERSISTED_QUERY_HASH_MISMATCH",
+                "provided sha does not match query".to_string(),
+            ));
+        }
+
+        next.run(ctx, request).await
+    }
+
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> Result<ExecutableDocument, ServerError> {
+        let document = next.run(ctx, query, variables).await?;
+        check_get_operations(ctx, &document)?;
+
+        if let Some((_, registered)) = self.register.lock().unwrap().as_mut() {
+            *registered = Some(query.to_string());
+        }
+
+        Ok(document)
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        // The limits and the schema rules run inside `next`, invalid queries are never stored
+        let result = next.run(ctx).await?;
+
+        let register = self.register.lock().unwrap().take();
+        if let Some((sha256_hash, Some(query))) = register {
+            self.store.put(&sha256_hash, &query).await;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Remove and parse the `persistedQuery` request extension
+pub(crate) fn take_persisted_query(
+    request: &mut Request,
+) -> Result<Option<PersistedQuery>, ServerError> {
+    let Some(value) = request.extensions.remove("persistedQuery") else {
+        return Ok(None);
+    };
+
+    let persisted_query: PersistedQuery = from_value(value).map_err(|_| {
+        server_error(
+            "PERSISTED_QUERY_INVALID",
+            "Invalid persistedQuery extension.".to_string(),
+        )
+    })?;
+    if persisted_query.version != 1 {
+        return Err(server_error(
+            "PERSISTED_QUERY_INVALID",
+            format!(
+                "Only version 1 of persistedQuery is supported, got {}.",
+                persisted_query.version
+            ),
+        ));
+    }
+
+    Ok(Some(persisted_query))
+}
+
+/// Reject documents containing mutations or subscriptions when the request came in over GET
+pub(crate) fn check_get_operations(
+    ctx: &ExtensionContext<'_>,
+    document: &ExecutableDocument,
+) -> Result<(), ServerError> {
+    let is_get = ctx.data_opt::<HttpGet>().is_some();
+    if is_get
+        && document
+            .operations
+            .iter()
+            .any(|(_, operation)| operation.node.ty != OperationType::Query)
+    {
+        return Err(server_error(
+            "METHOD_NOT_ALLOWED",
+            "Only queries can be sent with GET.".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Lowercase hex sha256 of the query, as computed by Apollo clients
+pub(crate) fn sha256_hex(query: &str) -> String {
+    format!("{:x}", Sha256::digest(query.as_bytes()))
+}
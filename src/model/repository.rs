@@ -0,0 +1,476 @@
+use crate::db;
+use crate::db::bookstore::{self, Book, BookKey, BookOrder, BookPage, BookPatch, SearchHit};
+use crate::db::filter::BookFilter;
+use crate::db::todo::{self, Todo};
+use crate::events::{BookChanged, Broker, MutationType, TodoChanged};
+use crate::isbn::Isbn;
+use crate::model::error::{book_insert_error, ApiError};
+use async_trait::async_trait;
+use sqlx::pool::PoolConnection;
+use sqlx::{PgPool, Postgres};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Weights of the title, author and tags matches of `search_books`, the defaults of `ts_rank` for A, B and C
+const SEARCH_WEIGHTS: [f32; 3] = [1.0, 0.4, 0.2];
+
+/// Where the resolvers read and write books, registered as `Arc<dyn BookRepository>` in the schema data
+#[async_trait]
+pub(crate) trait BookRepository: Send + Sync + 'static {
+    /// All books matching the optional filter ordered by title
+    async fn books(&self, filter: Option<&BookFilter>) -> Result<Vec<Book>, ApiError>;
+
+    /// The books with one of the given isbns, in no particular order
+    async fn books_by_isbns(&self, isbns: &[Isbn]) -> Result<Vec<Book>, ApiError>;
+
+    /// The books written by one of the given authors ordered by title
+    async fn books_by_authors(&self, authors: &[String]) -> Result<Vec<Book>, ApiError>;
+
+    /// See `bookstore::fetch_books_page`
+    async fn books_page(
+        &self,
+        order: BookOrder,
+        filter: Option<&BookFilter>,
+        after: Option<&BookKey>,
+        before: Option<&BookKey>,
+        limit: usize,
+        from_end: bool,
+    ) -> Result<BookPage, ApiError>;
+
+    /// At most `limit` books matching every word of `text` as a prefix, best matches first
+    async fn search_books(&self, text: &str, limit: usize) -> Result<Vec<SearchHit>, ApiError>;
+
+    /// Fails with `ApiError::DuplicateIsbn` if the isbn is taken
+    async fn create_book(&self, book: Book) -> Result<Book, ApiError>;
+
+    /// `None` if there is no book with this isbn
+    async fn update_book(&self, isbn: &Isbn, patch: &BookPatch) -> Result<Option<Book>, ApiError>;
+
+    /// Returns whether a book was deleted
+    async fn delete_book(&self, isbn: &Isbn) -> Result<bool, ApiError>;
+}
+
+/// Where the resolvers read and write todos, registered as `Arc<dyn TodoRepository>` in the schema data
+#[async_trait]
+pub(crate) trait TodoRepository: Send + Sync + 'static {
+    /// All todos ordered by id, optionally only those with the given `done` state
+    async fn todos(&self, done: Option<bool>) -> Result<Vec<Todo>, ApiError>;
+
+    async fn todo(&self, id: i64) -> Result<Option<Todo>, ApiError>;
+
+    async fn add_todo(&self, description: &str) -> Result<Todo, ApiError>;
+
+    /// `None` if there is no todo with this id
+    async fn toggle_todo(&self, id: i64) -> Result<Option<Todo>, ApiError>;
+
+    /// `None` if there is no todo with this id
+    async fn rename_todo(&self, id: i64, description: &str) -> Result<Option<Todo>, ApiError>;
+
+    /// Returns whether a todo was deleted
+    async fn delete_todo(&self, id: i64) -> Result<bool, ApiError>;
+}
+
+/// The `book` and `todos` tables, change events come from their triggers through `db::listener`
+pub(crate) struct PostgresRepository {
+    pool: PgPool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresRepository { pool }
+    }
+
+    async fn connection(&self) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+        db::acquire(&self.pool).await
+    }
+}
+
+#[async_trait]
+impl BookRepository for PostgresRepository {
+    async fn books(&self, filter: Option<&BookFilter>) -> Result<Vec<Book>, ApiError> {
+        Ok(bookstore::fetch_books(&mut *self.connection().await?, filter).await?)
+    }
+
+    async fn books_by_isbns(&self, isbns: &[Isbn]) -> Result<Vec<Book>, ApiError> {
+        Ok(bookstore::fetch_books_by_isbns(&mut *self.connection().await?, isbns).await?)
+    }
+
+    async fn books_by_authors(&self, authors: &[String]) -> Result<Vec<Book>, ApiError> {
+        Ok(bookstore::fetch_books_by_authors(&mut *self.connection().await?, authors).await?)
+    }
+
+    async fn books_page(
+        &self,
+        order: BookOrder,
+        filter: Option<&BookFilter>,
+        after: Option<&BookKey>,
+        before: Option<&BookKey>,
+        limit: usize,
+        from_end: bool,
+    ) -> Result<BookPage, ApiError> {
+        Ok(bookstore::fetch_books_page(
+            &mut *self.connection().await?,
+            order,
+            filter,
+            after,
+            before,
+            limit,
+            from_end,
+        )
+        .await?)
+    }
+
+    async fn search_books(&self, text: &str, limit: usize) -> Result<Vec<SearchHit>, ApiError> {
+        Ok(bookstore::search_books(&mut *self.connection().await?, text, limit as i64).await?)
+    }
+
+    async fn create_book(&self, book: Book) -> Result<Book, ApiError> {
+        bookstore::create_book(&mut *self.connection().await?, &book)
+            .await
+            .map_err(|e| book_insert_error(e, &book.isbn))
+    }
+
+    async fn update_book(&self, isbn: &Isbn, patch: &BookPatch) -> Result<Option<Book>, ApiError> {
+        Ok(bookstore::update_book(&mut *self.connection().await?, isbn, patch).await?)
+    }
+
+    async fn delete_book(&self, isbn: &Isbn) -> Result<bool, ApiError> {
+        Ok(bookstore::delete_book(&mut *self.connection().await?, isbn).await?)
+    }
+}
+
+#[async_trait]
+impl TodoRepository for PostgresRepository {
+    async fn todos(&self, done: Option<bool>) -> Result<Vec<Todo>, ApiError> {
+        Ok(todo::fetch_todos(&mut *self.connection().await?, done).await?)
+    }
+
+    async fn todo(&self, id: i64) -> Result<Option<Todo>, ApiError> {
+        Ok(todo::fetch_todo(&mut *self.connection().await?, id).await?)
+    }
+
+    async fn add_todo(&self, description: &str) -> Result<Todo, ApiError> {
+        Ok(todo::add_todo(&mut *self.connection().await?, description).await?)
+    }
+
+    async fn toggle_todo(&self, id: i64) -> Result<Option<Todo>, ApiError> {
+        Ok(todo::toggle_todo(&mut *self.connection().await?, id).await?)
+    }
+
+    async fn rename_todo(&self, id: i64, description: &str) -> Result<Option<Todo>, ApiError> {
+        Ok(todo::rename_todo(&mut *self.connection().await?, id, description).await?)
+    }
+
+    async fn delete_todo(&self, id: i64) -> Result<bool, ApiError> {
+        Ok(todo::delete_todo(&mut *self.connection().await?, id).await?)
+    }
+}
+
+/// Books and todos kept in the process and lost on restart, so the resolvers run without a database.
+/// Changes are published to the `Broker` like the database triggers do.
+/// Titles are compared byte by byte where Postgres uses the collation of the database,
+/// and search ranks approximate `ts_rank`.
+pub(crate) struct MemoryRepository {
+    books: Mutex<BTreeMap<Isbn, Book>>,
+    todos: Mutex<MemoryTodos>,
+    broker: Broker,
+}
+
+#[derive(Default)]
+struct MemoryTodos {
+    todos: BTreeMap<i64, Todo>,
+    /// Ids are never reused, like the `todos.id` sequence
+    last_id: i64,
+}
+
+impl MemoryRepository {
+    pub fn new(broker: Broker) -> Self {
+        MemoryRepository {
+            books: Mutex::new(BTreeMap::new()),
+            todos: Mutex::new(MemoryTodos::default()),
+            broker,
+        }
+    }
+
+    fn book_changed(&self, mutation_type: MutationType, isbn: &Isbn) {
+        self.broker.publish_book(BookChanged {
+            mutation_type,
+            isbn: isbn.clone(),
+        });
+    }
+
+    fn todo_changed(&self, mutation_type: MutationType, id: i64) {
+        self.broker.publish_todo(TodoChanged { mutation_type, id });
+    }
+
+    /// The books matching the filter sorted by title then isbn
+    fn books_by_title(&self, filter: Option<&BookFilter>) -> Vec<Book> {
+        let mut books: Vec<Book> = self
+            .books
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|book| filter.is_none_or(|filter| filter.matches(book)))
+            .cloned()
+            .collect();
+        books.sort_by(|a, b| (&a.title, &a.isbn).cmp(&(&b.title, &b.isbn)));
+        books
+    }
+}
+
+/// Position of a book in `order`, compared like the keyset bounds of `fetch_books_page`
+fn order_key(order: BookOrder, book: &Book) -> (&str, &Isbn) {
+    match order {
+        BookOrder::Isbn => ("", &book.isbn),
+        BookOrder::Title => (&book.title, &book.isbn),
+    }
+}
+
+fn key_position(order: BookOrder, key: &BookKey) -> (&str, &Isbn) {
+    match order {
+        BookOrder::Isbn => ("", &key.isbn),
+        BookOrder::Title => (&key.title, &key.isbn),
+    }
+}
+
+/// Wrap the words of `text` starting with one of `terms` in `<b>` tags, like `ts_headline`
+fn headline(text: &str, terms: &[String]) -> String {
+    let mut headline = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        headline.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        if terms
+            .iter()
+            .any(|term| word.to_lowercase().starts_with(term))
+        {
+            headline.push_str(&format!("<b>{word}</b>"));
+        } else {
+            headline.push_str(word);
+        }
+        rest = &rest[end..];
+    }
+    headline.push_str(rest);
+    headline
+}
+
+#[async_trait]
+impl BookRepository for MemoryRepository {
+    async fn books(&self, filter: Option<&BookFilter>) -> Result<Vec<Book>, ApiError> {
+        Ok(self.books_by_title(filter))
+    }
+
+    async fn books_by_isbns(&self, isbns: &[Isbn]) -> Result<Vec<Book>, ApiError> {
+        let books = self.books.lock().unwrap();
+        Ok(isbns
+            .iter()
+            .filter_map(|isbn| books.get(isbn).cloned())
+            .collect())
+    }
+
+    async fn books_by_authors(&self, authors: &[String]) -> Result<Vec<Book>, ApiError> {
+        Ok(self
+            .books_by_title(None)
+            .into_iter()
+            .filter(|book| authors.contains(&book.author))
+            .collect())
+    }
+
+    async fn books_page(
+        &self,
+        order: BookOrder,
+        filter: Option<&BookFilter>,
+        after: Option<&BookKey>,
+        before: Option<&BookKey>,
+        limit: usize,
+        from_end: bool,
+    ) -> Result<BookPage, ApiError> {
+        let mut books: Vec<Book> = self
+            .books_by_title(filter)
+            .into_iter()
+            .filter(|book| {
+                let position = order_key(order, book);
+                after.is_none_or(|key| position > key_position(order, key))
+                    && before.is_none_or(|key| position < key_position(order, key))
+            })
+            .collect();
+        books.sort_by(|a, b| order_key(order, a).cmp(&order_key(order, b)));
+
+        if from_end {
+            books.reverse();
+        }
+        let has_more = books.len() > limit;
+        books.truncate(limit);
+        if from_end {
+            books.reverse();
+        }
+
+        Ok(BookPage { books, has_more })
+    }
+
+    async fn search_books(&self, text: &str, limit: usize) -> Result<Vec<SearchHit>, ApiError> {
+        let terms = bookstore::search_terms(text);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut hits: Vec<SearchHit> = self
+            .books_by_title(None)
+            .into_iter()
+            .filter_map(|book| {
+                let tags = book
+                    .metadata
+                    .as_ref()
+                    .map(|metadata| metadata.tags.join(" "))
+                    .unwrap_or_default();
+                let fields = [book.title.as_str(), book.author.as_str(), tags.as_str()];
+                let field_words: Vec<Vec<String>> = fields
+                    .iter()
+                    .map(|field| bookstore::search_terms(field))
+                    .collect();
+
+                // Each term counts once with the weight of the most important field it matches
+                let mut rank = 0.0;
+                for term in &terms {
+                    rank += field_words
+                        .iter()
+                        .zip(SEARCH_WEIGHTS)
+                        .find(|(words, _)| words.iter().any(|word| word.starts_with(term)))
+                        .map(|(_, weight)| weight)?;
+                }
+
+                Some(SearchHit {
+                    headline: headline(&format!("{} - {}", book.title, book.author), &terms),
+                    rank,
+                    book,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.rank
+                .total_cmp(&a.rank)
+                .then_with(|| a.book.isbn.cmp(&b.book.isbn))
+        });
+        hits.truncate(limit);
+
+        Ok(hits)
+    }
+
+    async fn create_book(&self, book: Book) -> Result<Book, ApiError> {
+        {
+            let mut books = self.books.lock().unwrap();
+            if books.contains_key(&book.isbn) {
+                return Err(ApiError::DuplicateIsbn(book.isbn));
+            }
+            books.insert(book.isbn.clone(), book.clone());
+        }
+        self.book_changed(MutationType::Created, &book.isbn);
+
+        Ok(book)
+    }
+
+    async fn update_book(&self, isbn: &Isbn, patch: &BookPatch) -> Result<Option<Book>, ApiError> {
+        let updated = {
+            let mut books = self.books.lock().unwrap();
+            books.get_mut(isbn).map(|book| {
+                if let Some(title) = &patch.title {
+                    book.title.clone_from(title);
+                }
+                if let Some(author) = &patch.author {
+                    book.author.clone_from(author);
+                }
+                if let Some(metadata) = &patch.metadata {
+                    book.metadata.clone_from(metadata);
+                }
+                book.clone()
+            })
+        };
+        if updated.is_some() {
+            self.book_changed(MutationType::Updated, isbn);
+        }
+
+        Ok(updated)
+    }
+
+    async fn delete_book(&self, isbn: &Isbn) -> Result<bool, ApiError> {
+        let deleted = self.books.lock().unwrap().remove(isbn).is_some();
+        if deleted {
+            self.book_changed(MutationType::Deleted, isbn);
+        }
+
+        Ok(deleted)
+    }
+}
+
+#[async_trait]
+impl TodoRepository for MemoryRepository {
+    async fn todos(&self, done: Option<bool>) -> Result<Vec<Todo>, ApiError> {
+        Ok(self
+            .todos
+            .lock()
+            .unwrap()
+            .todos
+            .values()
+            .filter(|todo| done.is_none_or(|done| todo.done == done))
+            .cloned()
+            .collect())
+    }
+
+    async fn todo(&self, id: i64) -> Result<Option<Todo>, ApiError> {
+        Ok(self.todos.lock().unwrap().todos.get(&id).cloned())
+    }
+
+    async fn add_todo(&self, description: &str) -> Result<Todo, ApiError> {
+        let todo = {
+            let mut todos = self.todos.lock().unwrap();
+            todos.last_id += 1;
+            let todo = Todo {
+                id: todos.last_id,
+                description: description.to_string(),
+                done: false,
+            };
+            todos.todos.insert(todo.id, todo.clone());
+            todo
+        };
+        self.todo_changed(MutationType::Created, todo.id);
+
+        Ok(todo)
+    }
+
+    async fn toggle_todo(&self, id: i64) -> Result<Option<Todo>, ApiError> {
+        let updated = self.todos.lock().unwrap().todos.get_mut(&id).map(|todo| {
+            todo.done = !todo.done;
+            todo.clone()
+        });
+        if updated.is_some() {
+            self.todo_changed(MutationType::Updated, id);
+        }
+
+        Ok(updated)
+    }
+
+    async fn rename_todo(&self, id: i64, description: &str) -> Result<Option<Todo>, ApiError> {
+        let updated = self.todos.lock().unwrap().todos.get_mut(&id).map(|todo| {
+            todo.description = description.to_string();
+            todo.clone()
+        });
+        if updated.is_some() {
+            self.todo_changed(MutationType::Updated, id);
+        }
+
+        Ok(updated)
+    }
+
+    async fn delete_todo(&self, id: i64) -> Result<bool, ApiError> {
+        let deleted = self.todos.lock().unwrap().todos.remove(&id).is_some();
+        if deleted {
+            self.todo_changed(MutationType::Deleted, id);
+        }
+
+        Ok(deleted)
+    }
+}
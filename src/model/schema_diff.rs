@@ -0,0 +1,395 @@
+use async_graphql::parser::types::{
+    BaseType, FieldDefinition, InputValueDefinition, ServiceDocument, Type, TypeDefinition,
+    TypeKind, TypeSystemDefinition,
+};
+use async_graphql::parser::{parse_schema, Positioned};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How a schema change affects existing clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+    /// Existing operations keep working and get the same results
+    Safe,
+    /// Existing operations keep working but may get values they do not expect
+    Dangerous,
+    /// Existing operations may fail validation or break on the result
+    Breaking,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Severity::Safe => "safe",
+            Severity::Dangerous => "dangerous",
+            Severity::Breaking => "breaking",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SchemaChange {
+    pub(crate) severity: Severity,
+    /// Coordinate of the changed element, e.g. `Book.title` or `Query.books(filter:)`
+    pub(crate) path: String,
+    pub(crate) message: String,
+}
+
+/// Compare two SDL documents, returns the changes from `old` to `new` most severe first.
+/// Only the definitions are compared, descriptions and directives are ignored.
+pub(crate) fn diff_schema(
+    old: &str,
+    new: &str,
+) -> async_graphql::parser::Result<Vec<SchemaChange>> {
+    let old = types_by_name(parse_schema(old)?);
+    let new = types_by_name(parse_schema(new)?);
+    let mut changes = Changes::default();
+
+    for (name, old_type) in &old {
+        match new.get(name) {
+            None => changes.push(Severity::Breaking, name, "type was removed"),
+            Some(new_type) => diff_type(&mut changes, name, &old_type.kind, &new_type.kind),
+        }
+    }
+    for name in new.keys().filter(|name| !old.contains_key(*name)) {
+        changes.push(Severity::Safe, name, "type was added");
+    }
+
+    let mut changes = changes.0;
+    changes.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(changes)
+}
+
+#[derive(Default)]
+struct Changes(Vec<SchemaChange>);
+
+impl Changes {
+    fn push(&mut self, severity: Severity, path: &str, message: impl Into<String>) {
+        self.0.push(SchemaChange {
+            severity,
+            path: path.to_string(),
+            message: message.into(),
+        });
+    }
+}
+
+fn types_by_name(document: ServiceDocument) -> BTreeMap<String, TypeDefinition> {
+    document
+        .definitions
+        .into_iter()
+        .filter_map(|definition| match definition {
+            TypeSystemDefinition::Type(ty) => Some(ty.node),
+            _ => None,
+        })
+        .map(|ty| (ty.name.node.to_string(), ty))
+        .collect()
+}
+
+fn kind_name(kind: &TypeKind) -> &'static str {
+    match kind {
+        TypeKind::Scalar => "scalar",
+        TypeKind::Object(_) => "object",
+        TypeKind::Interface(_) => "interface",
+        TypeKind::Union(_) => "union",
+        TypeKind::Enum(_) => "enum",
+        TypeKind::InputObject(_) => "input object",
+    }
+}
+
+fn diff_type(changes: &mut Changes, name: &str, old: &TypeKind, new: &TypeKind) {
+    match (old, new) {
+        (TypeKind::Scalar, TypeKind::Scalar) => {}
+        (TypeKind::Object(old), TypeKind::Object(new)) => {
+            diff_names(changes, name, "interface", &old.implements, &new.implements);
+            diff_fields(changes, name, &old.fields, &new.fields);
+        }
+        (TypeKind::Interface(old), TypeKind::Interface(new)) => {
+            diff_names(changes, name, "interface", &old.implements, &new.implements);
+            diff_fields(changes, name, &old.fields, &new.fields);
+        }
+        (TypeKind::Union(old), TypeKind::Union(new)) => {
+            diff_names(changes, name, "member", &old.members, &new.members);
+        }
+        (TypeKind::Enum(old), TypeKind::Enum(new)) => {
+            let old_values: Vec<_> = old.values.iter().map(|v| v.node.value.clone()).collect();
+            let new_values: Vec<_> = new.values.iter().map(|v| v.node.value.clone()).collect();
+            diff_names(changes, name, "value", &old_values, &new_values);
+        }
+        (TypeKind::InputObject(old), TypeKind::InputObject(new)) => {
+            diff_input_values(changes, "input field", &old.fields, &new.fields, |field| {
+                format!("{name}.{field}")
+            });
+        }
+        (old, new) => changes.push(
+            Severity::Breaking,
+            name,
+            format!("changed from {} to {}", kind_name(old), kind_name(new)),
+        ),
+    }
+}
+
+/// Interfaces, union members and enum values: removing one breaks clients relying on it,
+/// adding one is dangerous because clients may not handle the new possibility.
+fn diff_names<T: PartialEq + fmt::Display>(
+    changes: &mut Changes,
+    name: &str,
+    what: &str,
+    old: &[Positioned<T>],
+    new: &[Positioned<T>],
+) {
+    let contains = |list: &[Positioned<T>], item: &T| list.iter().any(|i| i.node == *item);
+
+    for item in old.iter().filter(|item| !contains(new, &item.node)) {
+        changes.push(
+            Severity::Breaking,
+            name,
+            format!("{what} `{}` was removed", item.node),
+        );
+    }
+    for item in new.iter().filter(|item| !contains(old, &item.node)) {
+        changes.push(
+            Severity::Dangerous,
+            name,
+            format!("{what} `{}` was added", item.node),
+        );
+    }
+}
+
+fn diff_fields(
+    changes: &mut Changes,
+    name: &str,
+    old: &[Positioned<FieldDefinition>],
+    new: &[Positioned<FieldDefinition>],
+) {
+    for old_field in old {
+        let field_name = &old_field.node.name.node;
+        let path = format!("{name}.{field_name}");
+        let Some(new_field) = new.iter().find(|f| f.node.name.node == *field_name) else {
+            changes.push(Severity::Breaking, &path, "field was removed");
+            continue;
+        };
+
+        let (old_ty, new_ty) = (&old_field.node.ty.node, &new_field.node.ty.node);
+        if old_ty != new_ty {
+            let severity = if is_safe_output_change(old_ty, new_ty) {
+                Severity::Safe
+            } else {
+                Severity::Breaking
+            };
+            changes.push(
+                severity,
+                &path,
+                format!("type changed from `{old_ty}` to `{new_ty}`"),
+            );
+        }
+        diff_input_values(
+            changes,
+            "argument",
+            &old_field.node.arguments,
+            &new_field.node.arguments,
+            |argument| format!("{path}({argument}:)"),
+        );
+    }
+    for new_field in new {
+        let field_name = &new_field.node.name.node;
+        if !old.iter().any(|f| f.node.name.node == *field_name) {
+            changes.push(
+                Severity::Safe,
+                &format!("{name}.{field_name}"),
+                "field was added",
+            );
+        }
+    }
+}
+
+/// Arguments and input object fields, which are written by clients
+fn diff_input_values(
+    changes: &mut Changes,
+    what: &str,
+    old: &[Positioned<InputValueDefinition>],
+    new: &[Positioned<InputValueDefinition>],
+    path_of: impl Fn(&str) -> String,
+) {
+    for old_value in old {
+        let value_name = &old_value.node.name.node;
+        let path = path_of(value_name);
+        let Some(new_value) = new.iter().find(|v| v.node.name.node == *value_name) else {
+            changes.push(Severity::Breaking, &path, format!("{what} was removed"));
+            continue;
+        };
+
+        let (old_ty, new_ty) = (&old_value.node.ty.node, &new_value.node.ty.node);
+        if old_ty != new_ty {
+            let severity = if is_safe_input_change(old_ty, new_ty) {
+                Severity::Safe
+            } else {
+                Severity::Breaking
+            };
+            changes.push(
+                severity,
+                &path,
+                format!("type changed from `{old_ty}` to `{new_ty}`"),
+            );
+        }
+
+        let old_default = old_value.node.default_value.as_ref().map(|v| &v.node);
+        let new_default = new_value.node.default_value.as_ref().map(|v| &v.node);
+        if old_default != new_default {
+            changes.push(
+                Severity::Dangerous,
+                &path,
+                "default value changed, clients omitting it get a different behavior",
+            );
+        }
+    }
+    for new_value in new {
+        let value_name = &new_value.node.name.node;
+        if old.iter().any(|v| v.node.name.node == *value_name) {
+            continue;
+        }
+        let path = path_of(value_name);
+        if !new_value.node.ty.node.nullable && new_value.node.default_value.is_none() {
+            changes.push(
+                Severity::Breaking,
+                &path,
+                format!("required {what} was added"),
+            );
+        } else {
+            changes.push(
+                Severity::Dangerous,
+                &path,
+                format!("optional {what} was added"),
+            );
+        }
+    }
+}
+
+/// Clients reading a field only break when it may return something they did not expect:
+/// a nullable field may become non null but not the other way round.
+fn is_safe_output_change(old: &Type, new: &Type) -> bool {
+    let base_safe = match (&old.base, &new.base) {
+        (BaseType::Named(old), BaseType::Named(new)) => old == new,
+        (BaseType::List(old), BaseType::List(new)) => is_safe_output_change(old, new),
+        _ => false,
+    };
+    base_safe && (old.nullable || !new.nullable)
+}
+
+/// Clients writing a value only break when it accepts less than before:
+/// a non null argument may become nullable but not the other way round.
+fn is_safe_input_change(old: &Type, new: &Type) -> bool {
+    let base_safe = match (&old.base, &new.base) {
+        (BaseType::Named(old), BaseType::Named(new)) => old == new,
+        (BaseType::List(old), BaseType::List(new)) => is_safe_input_change(old, new),
+        _ => false,
+    };
+    base_safe && (new.nullable || !old.nullable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The single change between two SDL documents
+    fn only_change(old: &str, new: &str) -> (Severity, String) {
+        let changes = diff_schema(old, new).unwrap();
+        assert_eq!(changes.len(), 1, "{changes:?}");
+        (changes[0].severity, changes[0].path.clone())
+    }
+
+    #[test]
+    fn classifies_changes() {
+        let cases = [
+            (
+                "type Query { a: Int b: Int }",
+                "type Query { a: Int }",
+                Severity::Breaking,
+                "Query.b",
+            ),
+            (
+                "type Query { a(x: Int): Int }",
+                "type Query { a(x: Int!): Int }",
+                Severity::Breaking,
+                "Query.a(x:)",
+            ),
+            (
+                "type Query { a(x: Int!): Int }",
+                "type Query { a(x: Int): Int }",
+                Severity::Safe,
+                "Query.a(x:)",
+            ),
+            (
+                "enum Color { RED }",
+                "enum Color { RED GREEN }",
+                Severity::Dangerous,
+                "Color",
+            ),
+            (
+                "enum Color { RED GREEN }",
+                "enum Color { RED }",
+                Severity::Breaking,
+                "Color",
+            ),
+            (
+                "type Query { a: Int }",
+                "type Query { a(x: Int): Int }",
+                Severity::Dangerous,
+                "Query.a(x:)",
+            ),
+            (
+                "type Query { a: Int }",
+                "type Query { a(x: Int!): Int }",
+                Severity::Breaking,
+                "Query.a(x:)",
+            ),
+            (
+                "type Query { a: Int }",
+                "type Query { a: Int! }",
+                Severity::Safe,
+                "Query.a",
+            ),
+            (
+                "type Query { a: Int! }",
+                "type Query { a: Int }",
+                Severity::Breaking,
+                "Query.a",
+            ),
+            (
+                "type Query { a: Int }",
+                "type Query { a: Int b: Int }",
+                Severity::Safe,
+                "Query.b",
+            ),
+        ];
+
+        for (old, new, severity, path) in cases {
+            assert_eq!(
+                only_change(old, new),
+                (severity, path.to_string()),
+                "{old} => {new}"
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_descriptions() {
+        let old = "type Query { a: Int }";
+        let new = r#"type Query { "The a" a: Int }"#;
+        assert!(diff_schema(old, new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sorts_most_severe_first() {
+        let changes = diff_schema(
+            "type Query { a: Int b: Int }",
+            "type Query { a: Int c: Int }",
+        )
+        .unwrap();
+        let severities: Vec<Severity> = changes.iter().map(|c| c.severity).collect();
+        assert_eq!(severities, [Severity::Breaking, Severity::Safe]);
+    }
+}
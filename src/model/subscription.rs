@@ -0,0 +1,79 @@
+use crate::db::bookstore::Book;
+use crate::db::todo::Todo;
+use crate::events::{BookChanged, Broker, MutationType, TodoChanged};
+use crate::isbn::Isbn;
+use crate::model::loader::BookLoader;
+use crate::model::repository::TodoRepository;
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{Context, Object, Result, Subscription};
+use futures::stream::{Stream, StreamExt};
+use std::sync::Arc;
+
+#[Object]
+impl BookChanged {
+    async fn mutation_type(&self) -> MutationType {
+        self.mutation_type
+    }
+
+    async fn isbn(&self) -> &Isbn {
+        &self.isbn
+    }
+
+    /// The book as it is now, `null` once it has been deleted
+    async fn book(&self, ctx: &Context<'_>) -> Result<Option<Book>> {
+        let loader = ctx.data::<DataLoader<BookLoader>>()?;
+        Ok(loader.load_one(self.isbn.clone()).await?)
+    }
+}
+
+#[Object]
+impl TodoChanged {
+    async fn mutation_type(&self) -> MutationType {
+        self.mutation_type
+    }
+
+    async fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// The todo as it is now, `null` once it has been deleted
+    async fn todo(&self, ctx: &Context<'_>) -> Result<Option<Todo>> {
+        let todos = ctx.data::<Arc<dyn TodoRepository>>()?;
+        Ok(todos.todo(self.id).await?)
+    }
+}
+
+/// This is the Subscription object within your schema, served over websocket at `/ws`.
+/// Events come from the database triggers through `db::listener` into the `Broker` registered in the schema data,
+/// so writes made by any server instance or the CLI reach every subscriber.
+#[derive(Default)]
+pub(crate) struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Fires whenever a book is created, updated or deleted, optionally only for one kind of change
+    async fn book_changed(
+        &self,
+        ctx: &Context<'_>,
+        mutation_type: Option<MutationType>,
+    ) -> Result<impl Stream<Item = BookChanged>> {
+        let broker = ctx.data::<Broker>()?;
+        Ok(broker.book_events().filter(move |event| {
+            let matches = mutation_type.is_none_or(|t| t == event.mutation_type);
+            async move { matches }
+        }))
+    }
+
+    /// Fires whenever a todo is created, updated or deleted, optionally only for one kind of change
+    async fn todo_changed(
+        &self,
+        ctx: &Context<'_>,
+        mutation_type: Option<MutationType>,
+    ) -> Result<impl Stream<Item = TodoChanged>> {
+        let broker = ctx.data::<Broker>()?;
+        Ok(broker.todo_events().filter(move |event| {
+            let matches = mutation_type.is_none_or(|t| t == event.mutation_type);
+            async move { matches }
+        }))
+    }
+}
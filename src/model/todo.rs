@@ -0,0 +1,60 @@
+use crate::db::todo::Todo;
+use crate::model::error::ApiError;
+use crate::model::repository::TodoRepository;
+use crate::model::{list_complexity, UNBOUNDED_LIST_COMPLEXITY};
+use async_graphql::{Context, Object, Result};
+use std::sync::Arc;
+
+/// Queries over the `todos` table.
+#[derive(Default)]
+pub(crate) struct TodoQuery;
+
+#[Object]
+impl TodoQuery {
+    /// All todos ordered by id, pass `done` to only get finished or unfinished ones
+    #[graphql(
+        complexity = "list_complexity(UNBOUNDED_LIST_COMPLEXITY, child_complexity)",
+        cache_control(no_cache)
+    )]
+    async fn todos(&self, ctx: &Context<'_>, done: Option<bool>) -> Result<Vec<Todo>> {
+        let todos = ctx.data::<Arc<dyn TodoRepository>>()?;
+        Ok(todos.todos(done).await?)
+    }
+}
+
+/// Mutations over the `todos` table, todos are identified by id.
+#[derive(Default)]
+pub(crate) struct TodoMutation;
+
+#[Object]
+impl TodoMutation {
+    /// Add a new todo which is not done yet
+    async fn add_todo(&self, ctx: &Context<'_>, description: String) -> Result<Todo> {
+        let todos = ctx.data::<Arc<dyn TodoRepository>>()?;
+        Ok(todos.add_todo(&description).await?)
+    }
+
+    /// Flip the done state of a todo, fails with `NOT_FOUND` if there is no such todo
+    async fn toggle_todo(&self, ctx: &Context<'_>, id: i64) -> Result<Todo> {
+        let todos = ctx.data::<Arc<dyn TodoRepository>>()?;
+        todos
+            .toggle_todo(id)
+            .await?
+            .ok_or_else(|| ApiError::TodoNotFound(id).into())
+    }
+
+    /// Change the description of a todo, fails with `NOT_FOUND` if there is no such todo
+    async fn rename_todo(&self, ctx: &Context<'_>, id: i64, description: String) -> Result<Todo> {
+        let todos = ctx.data::<Arc<dyn TodoRepository>>()?;
+        todos
+            .rename_todo(id, &description)
+            .await?
+            .ok_or_else(|| ApiError::TodoNotFound(id).into())
+    }
+
+    /// Delete a todo, returns false if there was no such todo
+    async fn delete_todo(&self, ctx: &Context<'_>, id: i64) -> Result<bool> {
+        let todos = ctx.data::<Arc<dyn TodoRepository>>()?;
+        Ok(todos.delete_todo(id).await?)
+    }
+}
@@ -0,0 +1,293 @@
+use crate::config::QueryLimits;
+use crate::model::error::server_error;
+use crate::model::persisted_queries::{check_get_operations, sha256_hex, take_persisted_query};
+use crate::model::{schema_builder, DocumentMode, ServiceSchema};
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery, NextPrepareRequest,
+};
+use async_graphql::parser::parse_query;
+use async_graphql::parser::types::ExecutableDocument;
+use async_graphql::{Request, Response, ServerError, Variables};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The operation documents allowed in trusted documents mode.
+///
+/// The manifest is a JSON object mapping either the sha256 hash of a document or an operation name to the document:
+/// `{ "GetBook": "query GetBook($isbn: Isbn!) { book(isbn: $isbn) { title } }", "5e8f...": "{ hello }" }`
+pub(crate) struct TrustedDocuments {
+    by_key: BTreeMap<String, String>,
+    /// Every document by its sha256 hash, whatever it is keyed by in the manifest
+    by_hash: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum ManifestError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Io(e) => write!(f, "cannot read manifest: {e}"),
+            ManifestError::Json(e) => write!(f, "manifest is not a JSON object of strings: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl TrustedDocuments {
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        let content = std::fs::read_to_string(path).map_err(ManifestError::Io)?;
+        let by_key: BTreeMap<String, String> =
+            serde_json::from_str(&content).map_err(ManifestError::Json)?;
+
+        Ok(TrustedDocuments::new(by_key))
+    }
+
+    fn new(by_key: BTreeMap<String, String>) -> Self {
+        let by_hash = by_key
+            .values()
+            .map(|document| (sha256_hex(document), document.clone()))
+            .collect();
+
+        TrustedDocuments { by_key, by_hash }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_key.len()
+    }
+
+    /// Find the trusted document a request refers to, `None` if it is not allowed
+    fn resolve(&self, request: &mut Request) -> Result<Option<String>, ServerError> {
+        if let Some(persisted_query) = take_persisted_query(request)? {
+            return Ok(match request.query.is_empty() {
+                // Documents keyed by operation name can still be fetched by their hash
+                true => self
+                    .by_key
+                    .get(&persisted_query.sha256_hash)
+                    .or_else(|| self.by_hash.get(&persisted_query.sha256_hash))
+                    .cloned(),
+                false => self
+                    .allows(&request.query)
+                    .then(|| std::mem::take(&mut request.query)),
+            });
+        }
+
+        if request.query.is_empty() {
+            return Ok(request
+                .operation_name
+                .as_ref()
+                .and_then(|name| self.by_key.get(name))
+                .cloned());
+        }
+
+        Ok(self
+            .allows(&request.query)
+            .then(|| std::mem::take(&mut request.query)))
+    }
+
+    fn allows(&self, query: &str) -> bool {
+        self.by_hash.contains_key(&sha256_hex(query))
+    }
+}
+
+/// Only executes operations found in the `TrustedDocuments` manifest.
+/// Takes the place of `PersistedQueries`, clients can still refer to documents by hash but cannot register new ones.
+pub(crate) struct TrustedDocumentsOnly {
+    documents: Arc<TrustedDocuments>,
+}
+
+impl TrustedDocumentsOnly {
+    pub fn new(documents: Arc<TrustedDocuments>) -> Self {
+        TrustedDocumentsOnly { documents }
+    }
+}
+
+impl ExtensionFactory for TrustedDocumentsOnly {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(TrustedDocumentsOnly {
+            documents: self.documents.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Extension for TrustedDocumentsOnly {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        mut request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> Result<Request, ServerError> {
+        match self.documents.resolve(&mut request)? {
+            Some(document) => {
+                request.query = document;
+                next.run(ctx, request).await
+            }
+            None => Err(server_error(
+                "OPERATION_NOT_ALLOWED",
+                "Operation is not in the trusted documents manifest.".to_string(),
+            )),
+        }
+    }
+
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> Result<ExecutableDocument, ServerError> {
+        let document = next.run(ctx, query, variables).await?;
+        check_get_operations(ctx, &document)?;
+
+        Ok(document)
+    }
+}
+
+/// Stops every request right after validation, so documents can be checked without running resolvers
+struct ValidateOnly;
+
+impl ExtensionFactory for ValidateOnly {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ValidateOnly)
+    }
+}
+
+#[async_trait]
+impl Extension for ValidateOnly {
+    async fn execute(
+        &self,
+        _ctx: &ExtensionContext<'_>,
+        _operation_name: Option<&str>,
+        _next: NextExecute<'_>,
+    ) -> Response {
+        Response::default()
+    }
+}
+
+/// Check every manifest entry against the schema: the document must parse, a hash key must match the document,
+/// a name key must name one of its operations, and each operation must validate within the query limits.
+/// Returns the problems found per manifest key, an empty map means the manifest is valid.
+pub(crate) async fn validate_manifest(
+    limits: &QueryLimits,
+    documents: Arc<TrustedDocuments>,
+) -> BTreeMap<String, Vec<String>> {
+    let schema = schema_builder(limits, DocumentMode::Trusted(documents.clone()))
+        .extension(ValidateOnly)
+        .finish();
+    let mut problems = BTreeMap::new();
+
+    for (key, document) in documents.by_key.iter() {
+        let errors = validate_entry(&schema, key, document).await;
+        if !errors.is_empty() {
+            problems.insert(key.clone(), errors);
+        }
+    }
+
+    problems
+}
+
+async fn validate_entry(schema: &ServiceSchema, key: &str, document: &str) -> Vec<String> {
+    let parsed = match parse_query(document) {
+        Ok(parsed) => parsed,
+        Err(e) => return vec![e.to_string()],
+    };
+
+    let mut errors = vec![];
+    let operation_names: Vec<Option<String>> = parsed
+        .operations
+        .iter()
+        .map(|(name, _)| name.map(|name| name.to_string()))
+        .collect();
+
+    let is_hash = key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hash && sha256_hex(document) != key.to_ascii_lowercase() {
+        errors.push("key is not the sha256 hash of the document".to_string());
+    }
+    if !is_hash
+        && !operation_names
+            .iter()
+            .any(|name| name.as_deref() == Some(key))
+    {
+        errors.push(format!("document has no operation named {key}"));
+    }
+
+    for operation_name in operation_names {
+        let mut request = Request::new(document);
+        if let Some(name) = operation_name {
+            request = request.operation_name(name);
+        }
+        let response = schema.execute(request).await;
+        errors.extend(response.errors.into_iter().map(|e| e.message));
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::value;
+
+    const GET_BOOK: &str = "query GetBook($isbn: Isbn!) { book(isbn: $isbn) { title } }";
+
+    fn documents() -> TrustedDocuments {
+        TrustedDocuments::new(BTreeMap::from([
+            ("GetBook".to_string(), GET_BOOK.to_string()),
+            (sha256_hex("{ hello }"), "{ hello }".to_string()),
+        ]))
+    }
+
+    fn by_hash(hash: String) -> Request {
+        let mut request = Request::new("");
+        request.extensions.insert(
+            "persistedQuery".to_string(),
+            value!({ "version": 1, "sha256Hash": hash }),
+        );
+        request
+    }
+
+    #[test]
+    fn resolves_documents() {
+        let documents = documents();
+        let cases = [
+            (by_hash(sha256_hex("{ hello }")), Some("{ hello }")),
+            (by_hash(sha256_hex(GET_BOOK)), Some(GET_BOOK)),
+            (Request::new("").operation_name("GetBook"), Some(GET_BOOK)),
+            (Request::new(GET_BOOK), Some(GET_BOOK)),
+            (by_hash(sha256_hex("{ books { isbn } }")), None),
+            (Request::new("{ books { isbn } }"), None),
+            (Request::new("").operation_name("Unknown"), None),
+        ];
+
+        for (mut request, expected) in cases {
+            let key = format!(
+                "{:?} {:?}",
+                request.query,
+                request.extensions.get("persistedQuery")
+            );
+            assert_eq!(
+                documents.resolve(&mut request).unwrap().as_deref(),
+                expected,
+                "{key}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_persisted_query_versions() {
+        let mut request = Request::new("");
+        request.extensions.insert(
+            "persistedQuery".to_string(),
+            value!({ "version": 2, "sha256Hash": sha256_hex("{ hello }") }),
+        );
+        assert!(documents().resolve(&mut request).is_err());
+    }
+}
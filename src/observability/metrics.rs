@@ -1,8 +1,11 @@
 use axum::{extract::MatchedPath, http::Request, middleware::Next, response::IntoResponse};
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
-use std::time::Instant;
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
 
 const REQUEST_DURATION_METRIC_NAME: &str = "http_requests_duration_seconds";
+pub(crate) const POOL_ACQUIRE_WAIT_METRIC_NAME: &str = "db_pool_acquire_wait_seconds";
+const POOL_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
 
 pub(crate) fn create_prometheus_recorder() -> PrometheusHandle {
     const EXPONENTIAL_SECONDS: &[f64] = &[
@@ -20,6 +23,16 @@ pub(crate) fn create_prometheus_recorder() -> PrometheusHandle {
                 REQUEST_DURATION_METRIC_NAME
             )
         })
+        .set_buckets_for_metric(
+            Matcher::Full(POOL_ACQUIRE_WAIT_METRIC_NAME.to_string()),
+            EXPONENTIAL_SECONDS,
+        )
+        .unwrap_or_else(|_| {
+            panic!(
+                "Could not initialize the bucket for '{}'",
+                POOL_ACQUIRE_WAIT_METRIC_NAME
+            )
+        })
         .install_recorder()
         .expect("Could not install the Prometheus recorder")
 }
@@ -49,3 +62,28 @@ pub(crate) async fn track_metrics<B>(req: Request<B>, next: Next<B>) -> impl Int
 
     response
 }
+
+/// Publish the connection pool gauges every `POOL_SAMPLE_INTERVAL`,
+/// the acquire wait is recorded by `db::acquire` as connections are checked out
+pub(crate) fn spawn_pool_metrics(pool: PgPool) {
+    metrics::gauge!(
+        "db_pool_max_connections",
+        pool.options().get_max_connections() as f64
+    );
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POOL_SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let size = pool.size();
+            let idle = pool.num_idle();
+            metrics::gauge!("db_pool_connections", size as f64);
+            metrics::gauge!("db_pool_idle_connections", idle as f64);
+            metrics::gauge!(
+                "db_pool_in_use_connections",
+                (size as usize).saturating_sub(idle) as f64
+            );
+        }
+    });
+}
@@ -0,0 +1,210 @@
+use crate::auth::Identity;
+use crate::config::RateLimitConfig;
+use crate::routes::error_response;
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use lru::LruCache;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of tracked clients, the least recently seen one is forgotten to make room for a new one
+const MAX_TRACKED_CLIENTS: NonZeroUsize = match NonZeroUsize::new(10_000) {
+    Some(n) => n,
+    None => unreachable!(),
+};
+
+/// Who a request is charged to, only verified credentials get a bucket of their own
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ClientKey {
+    ApiKey(i64),
+    Subject(String),
+    Ip(IpAddr),
+}
+
+impl ClientKey {
+    fn kind(&self) -> &'static str {
+        match self {
+            ClientKey::ApiKey(_) => "api_key",
+            ClientKey::Subject(_) => "subject",
+            ClientKey::Ip(_) => "ip",
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per client, each holding up to `capacity` tokens refilled at `refill_per_second`
+pub(crate) struct RateLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    buckets: Mutex<LruCache<ClientKey, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(options: &RateLimitConfig) -> Self {
+        RateLimiter {
+            capacity: options.capacity as f64,
+            refill_per_second: options.refill_per_second as f64,
+            buckets: Mutex::new(LruCache::new(MAX_TRACKED_CLIENTS)),
+        }
+    }
+
+    /// Take `cost` tokens from the client's bucket, or tell how long to wait until they are available.
+    /// Costs above the capacity are charged as a full bucket so they are not rejected forever.
+    fn try_acquire(&self, client: &ClientKey, cost: usize) -> Result<(), Duration> {
+        let cost = (cost as f64).min(self.capacity);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = buckets.get_or_insert_mut(client.clone(), || Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            let missing = cost - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_second))
+        }
+    }
+
+    /// How long the client has to wait until a token is available, without taking it
+    fn wait_time(&self, client: &ClientKey) -> Option<Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get(client)?;
+        let tokens = self.refill(bucket, Instant::now());
+        (tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - tokens) / self.refill_per_second))
+    }
+
+    /// Charge a request with invalid credentials to its IP address, so guessing API keys is limited
+    /// like anonymous traffic. Tells how long to wait when the address already ran out of tokens.
+    pub fn charge_rejected(&self, address: IpAddr) -> Result<(), Duration> {
+        let client = ClientKey::Ip(address);
+        let result = self.try_acquire(&client, 1);
+        record_decision(&client, 1, &result);
+        result
+    }
+
+    /// Whether an IP address ran out of tokens, checked before looking up its credentials
+    pub fn blocked(&self, address: IpAddr) -> Option<Duration> {
+        self.wait_time(&ClientKey::Ip(address))
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity)
+    }
+}
+
+/// Lets the GraphQL request charge its cost to the client once it is known.
+/// A rejection is remembered so `rate_limit` can turn the response into a 429.
+#[derive(Clone)]
+pub(crate) struct RateLimitTicket {
+    limiter: Arc<RateLimiter>,
+    client: ClientKey,
+    retry_after: Arc<Mutex<Option<Duration>>>,
+}
+
+impl RateLimitTicket {
+    pub fn charge(&self, cost: usize) -> Result<(), Duration> {
+        let result = self.limiter.try_acquire(&self.client, cost);
+        record_decision(&self.client, cost, &result);
+        if let Err(retry_after) = result {
+            *self.retry_after.lock().unwrap() = Some(retry_after);
+        }
+        result
+    }
+}
+
+fn record_decision(client: &ClientKey, cost: usize, result: &Result<(), Duration>) {
+    let decision = match result {
+        Ok(()) => "allowed",
+        Err(_) => "rejected",
+    };
+    let labels = [("client", client.kind()), ("decision", decision)];
+
+    metrics::increment_counter!("rate_limit_decisions_total", &labels);
+    metrics::counter!("rate_limit_tokens_total", cost as u64, &labels);
+}
+
+/// Charge every request one token to its client (API key, JWT subject or IP address) and hand a
+/// `RateLimitTicket` to the GraphQL handlers, which charge the query or subscription complexity on top.
+/// Has to run after `auth::authenticate` to see the identity, requests with credentials it rejected
+/// never get here and are charged to their IP address by `authenticate`.
+pub(crate) async fn rate_limit<B>(
+    State(limiter): State<Arc<RateLimiter>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let client = match req.extensions().get::<Identity>() {
+        Some(Identity {
+            api_key: Some(id), ..
+        }) => ClientKey::ApiKey(*id),
+        Some(identity) => ClientKey::Subject(identity.subject.clone()),
+        None => {
+            let ConnectInfo(address) = req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .expect("the server has to be started with connect info");
+            ClientKey::Ip(address.ip())
+        }
+    };
+
+    let ticket = RateLimitTicket {
+        limiter,
+        client,
+        retry_after: Arc::default(),
+    };
+    if let Err(retry_after) = ticket.charge(1) {
+        return too_many_requests(
+            error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "RATE_LIMITED",
+                rate_limited_message(retry_after),
+            ),
+            retry_after,
+        );
+    }
+
+    req.extensions_mut().insert(ticket.clone());
+    let response = next.run(req).await;
+
+    let retry_after = *ticket.retry_after.lock().unwrap();
+    match retry_after {
+        Some(retry_after) => too_many_requests(response, retry_after),
+        None => response,
+    }
+}
+
+pub(crate) fn rate_limited_message(retry_after: Duration) -> String {
+    format!(
+        "rate limit exceeded, retry in {} seconds",
+        retry_after_secs(retry_after)
+    )
+}
+
+pub(crate) fn too_many_requests(mut response: Response, retry_after: Duration) -> Response {
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(retry_after_secs(retry_after)),
+    );
+    response
+}
+
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
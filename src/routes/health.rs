@@ -0,0 +1,146 @@
+use crate::db;
+use crate::db::migration::migration_status;
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+use tracing::warn;
+
+/// Time a dependency has to answer before it is reported as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Live {
+    status: &'static str,
+}
+
+/// The process is up and serving requests, dependencies are not checked
+pub(crate) async fn liveness() -> impl IntoResponse {
+    (StatusCode::OK, Json(Live { status: "ok" }))
+}
+
+#[derive(Serialize)]
+struct Ready {
+    status: &'static str,
+    /// Absent when the server runs without a database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checks: Option<Checks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool: Option<PoolStats>,
+}
+
+#[derive(Serialize)]
+struct Checks {
+    database: Check,
+    migrations: Check,
+}
+
+/// Outcome of a dependency check, the reason of a failure is only logged
+#[derive(Serialize)]
+struct Check {
+    /// `up`, `down` or `timeout`
+    status: &'static str,
+    latency_ms: f64,
+}
+
+#[derive(Serialize)]
+struct PoolStats {
+    size: u32,
+    idle: usize,
+    in_use: usize,
+    max: u32,
+}
+
+/// The service can take traffic: Postgres answers and every embedded migration is applied unchanged.
+/// Migrations newer than this binary only log a warning, so old instances stay ready during a rollout.
+/// Answers 503 with the status of every check otherwise, the failures are logged.
+/// Always ready without a database, when books and todos are kept in memory.
+pub(crate) async fn readiness(Extension(pool): Extension<Option<PgPool>>) -> impl IntoResponse {
+    let Some(pool) = pool else {
+        let ready = Ready {
+            status: "ready",
+            checks: None,
+            pool: None,
+        };
+        return (StatusCode::OK, Json(ready));
+    };
+
+    let (database, migrations) = tokio::join!(
+        check("database", async {
+            let mut conn = db::acquire(&pool).await?;
+            sqlx::query("SELECT 1 + 1").execute(&mut *conn).await?;
+            Ok(None)
+        }),
+        check("migrations", async {
+            let status = migration_status(&pool).await?;
+            if !status.is_compatible() {
+                return Ok(Some(format!("{status:?}")));
+            }
+            if !status.unknown.is_empty() {
+                warn!(
+                    "database has migrations {:?} unknown to this binary, assuming a newer release applied them",
+                    status.unknown
+                );
+            }
+            Ok(None)
+        }),
+    );
+
+    let size = pool.size();
+    let idle = pool.num_idle();
+    let ready = Ready {
+        status: if database.status == "up" && migrations.status == "up" {
+            "ready"
+        } else {
+            "degraded"
+        },
+        checks: Some(Checks {
+            database,
+            migrations,
+        }),
+        pool: Some(PoolStats {
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+            max: pool.options().get_max_connections(),
+        }),
+    };
+
+    let status = match ready.status {
+        "ready" => StatusCode::OK,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(ready))
+}
+
+/// Run the check of `dependency` with `CHECK_TIMEOUT`, the check returns why it is unhealthy if it is
+async fn check(
+    dependency: &str,
+    run: impl Future<Output = Result<Option<String>, sqlx::Error>>,
+) -> Check {
+    let start = Instant::now();
+    let result = timeout(CHECK_TIMEOUT, run).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let status = match result {
+        Ok(Ok(None)) => "up",
+        Ok(Ok(Some(problem))) => {
+            warn!(dependency, "readiness check failed: {problem}");
+            "down"
+        }
+        Ok(Err(e)) => {
+            warn!(dependency, "readiness check failed: {e}");
+            "down"
+        }
+        Err(_) => {
+            warn!(
+                dependency,
+                "readiness check got no answer within {CHECK_TIMEOUT:?}"
+            );
+            "timeout"
+        }
+    };
+    Check { status, latency_ms }
+}
@@ -1,29 +1,25 @@
 use axum::{
-    extract::Extension,
-    http::StatusCode,
-    response::{Html, IntoResponse},
-    Json,
+    extract::{ws::WebSocketUpgrade, Extension},
+    http::{StatusCode, Uri},
+    response::{Html, IntoResponse, Response},
 };
-use serde::Serialize;
 
-use crate::model::ServiceSchema;
-use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use crate::auth::Identity;
+use crate::model::{server_error, HttpGet, ServiceSchema};
+use crate::rate_limit::RateLimitTicket;
+use async_graphql::http::{
+    parse_query_string, playground_source, GraphQLPlaygroundConfig, ALL_WEBSOCKET_PROTOCOLS,
+};
+use async_graphql::Data;
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
 
 use opentelemetry::trace::TraceContextExt;
 use tracing::{info, span, Instrument, Level};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-#[derive(Serialize)]
-struct Health {
-    healthy: bool,
-}
+mod health;
 
-pub(crate) async fn health() -> impl IntoResponse {
-    let health = Health { healthy: true };
-
-    (StatusCode::OK, Json(health))
-}
+pub(crate) use health::{liveness, readiness};
 
 pub(crate) async fn graphql_playground() -> impl IntoResponse {
     Html(playground_source(
@@ -33,12 +29,86 @@ pub(crate) async fn graphql_playground() -> impl IntoResponse {
 
 pub(crate) async fn graphql_handler(
     Extension(schema): Extension<ServiceSchema>, // (2)
+    identity: Option<Extension<Identity>>,
+    ticket: Option<Extension<RateLimitTicket>>,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
-    let span = span!(Level::INFO, "graphql_execution"); // (1)
+    execute(schema, req.into_inner(), identity, ticket).await
+}
+
+/// GET on `/` executes the query passed in the query string, e.g. a persisted query hash,
+/// so responses can be cached by CDNs. Without a query string the playground is served.
+pub(crate) async fn graphql_get_handler(
+    Extension(schema): Extension<ServiceSchema>,
+    identity: Option<Extension<Identity>>,
+    ticket: Option<Extension<RateLimitTicket>>,
+    uri: Uri,
+) -> Response {
+    match uri.query().filter(|query| !query.is_empty()) {
+        Some(query) => match parse_query_string(query) {
+            Ok(req) => execute(schema, req.data(HttpGet), identity, ticket)
+                .await
+                .into_response(),
+            Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        },
+        None => graphql_playground().await.into_response(),
+    }
+}
+
+/// Subscriptions over WebSocket. The identity and rate limit ticket of the upgrade request are kept
+/// for the whole connection, so every subscription is authorized and charged its complexity like a query.
+pub(crate) async fn graphql_ws_handler(
+    Extension(schema): Extension<ServiceSchema>,
+    identity: Option<Extension<Identity>>,
+    ticket: Option<Extension<RateLimitTicket>>,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let mut data = Data::default();
+    if let Some(Extension(identity)) = identity {
+        data.insert(identity);
+    }
+    if let Some(Extension(ticket)) = ticket {
+        data.insert(ticket);
+    }
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .with_data(data)
+                .serve()
+        })
+}
+
+/// Run a request, the identity set by `auth::authenticate` is made available to the guards
+/// and the ticket set by `rate_limit::rate_limit` to the query cost extension
+async fn execute(
+    schema: ServiceSchema,
+    mut req: async_graphql::Request,
+    identity: Option<Extension<Identity>>,
+    ticket: Option<Extension<RateLimitTicket>>,
+) -> GraphQLResponse {
+    let span = span!(
+        Level::INFO,
+        "graphql_execution",
+        subject = tracing::field::Empty,
+        api_key = tracing::field::Empty
+    ); // (1)
+    if let Some(Extension(identity)) = identity {
+        span.record("subject", identity.subject.as_str());
+        if let Some(id) = identity.api_key {
+            span.record("api_key", id);
+        }
+        req = req.data(identity);
+    }
+    if let Some(Extension(ticket)) = ticket {
+        req = req.data(ticket);
+    }
+
     info!("Processing GraphQL request");
 
-    let response = async move { schema.execute(req.into_inner()).await } // (2)
+    let response = async move { schema.execute(req).await } // (2)
         .instrument(span.clone())
         .await;
     info!("Processing GraphQL request finished");
@@ -54,3 +124,13 @@ pub(crate) async fn graphql_handler(
         )
         .into()
 }
+
+/// Response rejecting a request before it reached the schema, in the GraphQL response format
+/// so clients handle it like any other error
+pub(crate) fn error_response(status: StatusCode, code: &str, message: String) -> Response {
+    let response = async_graphql::Response::from_errors(vec![server_error(code, message)]);
+
+    let mut response = GraphQLResponse::from(response).into_response();
+    *response.status_mut() = status;
+    response
+}